@@ -1,7 +1,7 @@
 use serde::de::{MapAccess, Visitor};
 use serde::Deserialize;
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt;
 
 pub use crate::{Locale, Localized};
@@ -33,9 +33,9 @@ where
                 M: MapAccess<'de>,
             {
                 let mut default = None;
-                // False positive, the hash function won't read the mutable fields
+                // False positive, the ordering won't read the mutable fields
                 #[allow(clippy::mutable_key_type)]
-                let mut content = HashMap::new();
+                let mut content = BTreeMap::new();
                 while let Some((k, v)) = map.next_entry::<String, T>()? {
                     if k.to_lowercase() == "default" {
                         default = Some(v);
@@ -56,7 +56,7 @@ where
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     use super::{Locale, Localized};
 
@@ -73,7 +73,7 @@ mod test {
         assert_eq!(
             Localized::<String> {
                 default: Some("Kusa".into()),
-                content: HashMap::from([
+                content: BTreeMap::from([
                     (Locale::new("zh-CN"), "草".into()),
                     (Locale::new("en-US"), "Grass".into()),
                 ]),
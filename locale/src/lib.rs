@@ -1,16 +1,28 @@
 mod de;
 mod error;
+mod ser;
 
 use isolang::Language;
 use serde::Deserialize;
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::hash::Hash;
 use std::str::FromStr;
 use std::sync::OnceLock;
 
 pub use error::LocaleError;
 
+/// Deprecated ISO 639 language codes and their replacements.
+static LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("in", "id"),
+    ("iw", "he"),
+    ("ji", "yi"),
+    ("jw", "jv"),
+    ("mo", "ro"),
+];
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Locale {
     locale: String,
@@ -18,10 +30,104 @@ pub struct Locale {
     language: OnceLock<Option<Language>>,
 }
 
+/// How [`Locale::format`] writes a locale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocaleFormat {
+    /// Subtags joined by `_`, as in `zh_Hant_TW`
+    Underscore,
+    /// Subtags joined by `-`, as in `zh-Hant-TW`
+    Hyphen,
+    /// As [`Locale::to_posix`] writes it
+    Posix,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Subtags<'a> {
+    language: &'a str,
+    region: Option<&'a str>,
+    encoding: Option<&'a str>,
+    modifier: Option<&'a str>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Localized<T> {
     default: Option<T>,
-    content: HashMap<Locale, T>,
+    content: BTreeMap<Locale, T>,
+}
+
+impl<'a> Subtags<'a> {
+    /// Splits `lang[-_]REGION[.ENCODING][@modifier]` into its parts.
+    fn parse(locale: &'a str) -> Self {
+        let (locale, modifier) = match locale.split_once('@') {
+            Some((l, m)) => (l, Some(m)),
+            None => (locale, None),
+        };
+        let (locale, encoding) = match locale.split_once('.') {
+            Some((l, e)) => (l, Some(e)),
+            None => (locale, None),
+        };
+        let mut parts = locale.split(['-', '_']);
+        let language = parts.next().unwrap_or_default();
+        let region = parts.find(|p| {
+            (p.len() == 2 && p.chars().all(|c| c.is_ascii_alphabetic()))
+                || (p.len() == 3 && p.chars().all(|c| c.is_ascii_digit()))
+        });
+        Self {
+            language,
+            region,
+            encoding,
+            modifier,
+        }
+    }
+}
+
+/// Normalizes the tag part of a locale to `lang-Script-REGION`, keeping any
+/// POSIX encoding or modifier as is.
+fn normalize(locale: &str) -> String {
+    let split = locale.find(['.', '@']).unwrap_or(locale.len());
+    let (tag, suffix) = locale.split_at(split);
+    let mut ret = String::with_capacity(locale.len());
+    for (i, part) in tag.split(['-', '_']).enumerate() {
+        if i == 0 {
+            ret.push_str(&part.to_lowercase());
+            continue;
+        }
+        ret.push('-');
+        let alphabetic = part.chars().all(|c| c.is_ascii_alphabetic());
+        match part.len() {
+            2 if alphabetic => ret.push_str(&part.to_uppercase()),
+            4 if alphabetic => {
+                ret.push_str(&part[..1].to_uppercase());
+                ret.push_str(&part[1..].to_lowercase());
+            }
+            _ => ret.push_str(part),
+        }
+    }
+    ret.push_str(suffix);
+    ret
+}
+
+/// Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Whether `locale` is empty or the `C` or `POSIX` locale, with any
+/// encoding or modifier, as in `C.UTF-8`.
+fn is_unspecified(locale: &str) -> bool {
+    let tag = locale.split(['.', '@']).next().unwrap_or_default();
+    tag.is_empty() || tag.eq_ignore_ascii_case("c") || tag.eq_ignore_ascii_case("posix")
 }
 
 impl PartialEq for Locale {
@@ -32,6 +138,18 @@ impl PartialEq for Locale {
 
 impl Eq for Locale {}
 
+impl PartialOrd for Locale {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Locale {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.locale.cmp(&other.locale)
+    }
+}
+
 impl Hash for Locale {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.locale.hash(state)
@@ -39,23 +157,148 @@ impl Hash for Locale {
 }
 
 impl Locale {
+    /// Creates a locale, normalizing the case of its subtags and using `-`
+    /// as the delimiter, so `en_us` and `en-US` are the same locale. The
+    /// empty string and the `C` and `POSIX` locales name no language and
+    /// become [`Locale::und`].
     pub fn new<S: AsRef<str>>(locale: S) -> Self {
+        let locale = locale.as_ref();
+        let locale = if is_unspecified(locale) {
+            "und".to_owned()
+        } else {
+            normalize(locale)
+        };
         Self {
-            locale: locale.as_ref().into(),
+            locale,
             language: OnceLock::new(),
         }
     }
 
+    /// Like [`Locale::new`], but also replaces deprecated language codes
+    /// with their modern equivalents, so `iw-IL` becomes `he-IL`.
+    pub fn new_with_aliases<S: AsRef<str>>(locale: S) -> Self {
+        let locale = normalize(locale.as_ref());
+        let split = locale.find(['-', '.', '@']).unwrap_or(locale.len());
+        let (language, rest) = locale.split_at(split);
+        match LANGUAGE_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == language)
+        {
+            Some((_, modern)) => Self::new(format!("{}{}", modern, rest)),
+            None => Self::new(locale),
+        }
+    }
+
+    /// Parses a locale, rejecting tags that are not well-formed. The
+    /// language must be 2-3 letters and the region 2 letters or 3 digits,
+    /// optionally with a 4-letter script in between, as in `zh-Hant-TW`.
+    /// POSIX encodings and modifiers are accepted, but the empty string,
+    /// `C` and `POSIX` are not, as they name no language.
+    pub fn parse_strict(s: &str) -> Result<Self, LocaleError> {
+        let tag = s.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = tag.split(['-', '_']).peekable();
+        let language = parts.next().unwrap_or_default();
+        if !((2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic()))
+        {
+            return Err(LocaleError::InvalidLocale);
+        }
+        if let Some(script) = parts.peek() {
+            if script.len() == 4 && script.chars().all(|c| c.is_ascii_alphabetic()) {
+                parts.next();
+            }
+        }
+        if let Some(region) = parts.next() {
+            let valid = (region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic()))
+                || (region.len() == 3 && region.chars().all(|c| c.is_ascii_digit()));
+            if !valid {
+                return Err(LocaleError::InvalidLocale);
+            }
+        }
+        if parts.next().is_some() {
+            return Err(LocaleError::InvalidLocale);
+        }
+        Ok(Self::new(s))
+    }
+
+    /// The undetermined locale, `und` in BCP 47.
+    pub fn und() -> Self {
+        Self::new("und")
+    }
+
+    pub fn is_und(&self) -> bool {
+        self.locale == "und"
+    }
+
+    /// Returns just the language of the locale, so `en_US.UTF-8` becomes
+    /// `en`.
+    pub fn language_only(&self) -> Self {
+        Self::new(Subtags::parse(&self.locale).language)
+    }
+
     pub fn to_locale(&self) -> &str {
         &self.locale
     }
 
+    /// The language subtag, `zh` for `zh_CN.UTF-8`.
+    pub fn language(&self) -> &str {
+        Subtags::parse(&self.locale).language
+    }
+
+    /// The region subtag, `CN` for `zh_CN.UTF-8`.
+    pub fn region(&self) -> Option<&str> {
+        Subtags::parse(&self.locale).region
+    }
+
+    /// The POSIX charset, `UTF-8` for `zh_CN.UTF-8`.
+    pub fn encoding(&self) -> Option<&str> {
+        Subtags::parse(&self.locale).encoding
+    }
+
+    /// The POSIX modifier, `euro` for `de_DE.UTF-8@euro`.
+    pub fn modifier(&self) -> Option<&str> {
+        Subtags::parse(&self.locale).modifier
+    }
+
     fn get_language(&self) -> Option<&Language> {
         self.language
             .get_or_init(|| Language::from_locale(&self.locale))
             .as_ref()
     }
 
+    /// Formats the locale as `lang_REGION.ENCODING@modifier`, omitting the
+    /// parts that are absent.
+    pub fn to_posix(&self) -> String {
+        let subtags = Subtags::parse(&self.locale);
+        let mut ret = subtags.language.to_lowercase();
+        if let Some(region) = subtags.region {
+            ret.push('_');
+            ret.push_str(&region.to_uppercase());
+        }
+        if let Some(encoding) = subtags.encoding {
+            ret.push('.');
+            ret.push_str(encoding);
+        }
+        if let Some(modifier) = subtags.modifier {
+            ret.push('@');
+            ret.push_str(modifier);
+        }
+        ret
+    }
+
+    /// Writes the locale in `format`. Any POSIX encoding or modifier is
+    /// kept as is.
+    pub fn format(&self, format: LocaleFormat) -> String {
+        match format {
+            LocaleFormat::Underscore => {
+                let split = self.locale.find(['.', '@']).unwrap_or(self.locale.len());
+                let (tag, suffix) = self.locale.split_at(split);
+                tag.replace('-', "_") + suffix
+            }
+            LocaleFormat::Hyphen => self.locale.clone(),
+            LocaleFormat::Posix => self.to_posix(),
+        }
+    }
+
     pub fn to_iso639_1(&self) -> Option<&str> {
         self.get_language().and_then(|l| l.to_639_1())
     }
@@ -65,14 +308,23 @@ impl Locale {
     }
 }
 
+/// Formats the BCP 47 style tag, without any POSIX encoding or modifier,
+/// or with `{:#}` the whole locale as [`Locale::to_posix`] does.
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return f.write_str(&self.to_posix());
+        }
+        let tag = self.locale.split(['.', '@']).next().unwrap_or_default();
+        f.write_str(tag)
+    }
+}
+
 impl FromStr for Locale {
     type Err = LocaleError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self {
-            locale: s.into(),
-            language: OnceLock::new(),
-        })
+        Ok(Self::new(s))
     }
 }
 
@@ -80,22 +332,132 @@ impl<T> Localized<T> {
     pub fn new(default: Option<T>) -> Self {
         Self {
             default,
-            content: HashMap::new(),
+            content: BTreeMap::new(),
         }
     }
 
+    /// Number of values, counting the default as one.
     pub fn len(&self) -> usize {
         self.content.len() + self.default.as_ref().map(|_| 1).unwrap_or(0)
     }
 
+    /// Number of translations, without the default.
+    pub fn content_len(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn has_default(&self) -> bool {
+        self.default.is_some()
+    }
+
+    /// Whether there is neither a default nor a translation, e.g. to skip
+    /// the field with `#[serde(skip_serializing_if = "Localized::is_empty")]`.
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    pub fn get(&self, locale: &Locale) -> Option<&T> {
+        self.content.get(locale)
+    }
+
+    /// Best-effort lookup tolerating slightly-off requests: the exact
+    /// translation, else the one for the language alone, else the one of
+    /// the same language whose tag is closest by edit distance, as for
+    /// `en-USA` against `en-US`. Falls back to the default, never to
+    /// another language.
+    pub fn get_closest(&self, locale: &Locale) -> Option<&T> {
+        if let Some(value) = self
+            .get(locale)
+            .or_else(|| self.get(&locale.language_only()))
+        {
+            return Some(value);
+        }
+        let tag = locale.to_string().to_lowercase();
+        self.content
+            .iter()
+            .filter(|(l, _)| l.language() == locale.language())
+            // The first of equally close ones in sort order
+            .min_by_key(|(l, _)| edit_distance(&l.to_string().to_lowercase(), &tag))
+            .map(|(_, value)| value)
+            .or(self.default.as_ref())
+    }
+
     pub fn set(&mut self, locale: Locale, content: T) -> Option<T> {
         self.content.insert(locale, content)
     }
 
+    /// Returns the translation for `locale`, inserting the result of `f`
+    /// if there is none.
+    pub fn get_or_insert_with<F: FnOnce() -> T>(&mut self, locale: Locale, f: F) -> &mut T {
+        self.content.entry(locale).or_insert_with(f)
+    }
+
+    /// Returns the default, inserting the result of `f` if there is none.
+    pub fn default_or_insert_with<F: FnOnce() -> T>(&mut self, f: F) -> &mut T {
+        self.default.get_or_insert_with(f)
+    }
+
+    /// Iterates over the translated locales in sort order. The default is
+    /// not included.
+    pub fn keys(&self) -> impl Iterator<Item = &Locale> {
+        self.content.keys()
+    }
+
+    /// Iterates over the translations in the order of [`Localized::keys`].
+    /// The default is not included.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.content.values()
+    }
+
+    /// Collects [`Localized::keys`].
+    pub fn locales(&self) -> Vec<&Locale> {
+        self.keys().collect()
+    }
+
+    /// Collects the translations with their locales in sort order. The
+    /// default is not included.
+    pub fn sorted_entries(&self) -> Vec<(&Locale, &T)> {
+        self.content.iter().collect()
+    }
+
+    /// Merges `other` into `self`. Translations in `other` overwrite those
+    /// for the same locale, and its default replaces ours only if present.
+    pub fn merge(&mut self, other: Localized<T>) {
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+        self.content.extend(other.content);
+    }
+
+    /// Copies the default and the translations whose locale matches
+    /// `predicate`, e.g. to ship only some languages.
+    pub fn filter<F: Fn(&Locale) -> bool>(&self, predicate: F) -> Localized<T>
+    where
+        T: Clone,
+    {
+        Localized {
+            default: self.default.clone(),
+            content: self
+                .content
+                .iter()
+                .filter(|(locale, _)| predicate(locale))
+                .map(|(locale, value)| (locale.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Keeps only the translations for which `f` returns true, leaving the
+    /// default alone.
+    pub fn retain<F: FnMut(&Locale, &mut T) -> bool>(&mut self, f: F) {
+        self.content.retain(f);
+    }
+
+    /// Like [`Localized::merge`], but returns the result.
+    pub fn merged(mut self, other: Localized<T>) -> Self {
+        self.merge(other);
+        self
+    }
+
     pub fn generate_hashmap<F>(&self, transform: F) -> Result<HashMap<&str, &T>, LocaleError>
     where
         F: Fn(&Locale) -> &str,
@@ -110,6 +472,23 @@ impl<T> Localized<T> {
     pub fn get_default(&self) -> Option<&T> {
         self.default.as_ref()
     }
+
+    /// Returns the default, or a deterministic stand-in if there is none:
+    /// the `en` or `en-US` translation, else the first locale in sort order.
+    pub fn best_default(&self) -> Option<&T> {
+        self.default
+            .as_ref()
+            .or_else(|| self.content.get(&Locale::new("en")))
+            .or_else(|| self.content.get(&Locale::new("en-US")))
+            .or_else(|| self.content.values().next())
+    }
+}
+
+impl Localized<String> {
+    /// Drops the translations that are empty strings.
+    pub fn retain_non_empty(&mut self) {
+        self.retain(|_, value| !value.is_empty());
+    }
 }
 
 impl<T: PartialEq> PartialEq for Localized<T> {
@@ -119,3 +498,430 @@ impl<T: PartialEq> PartialEq for Localized<T> {
 }
 
 impl<T: Eq> Eq for Localized<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::{edit_distance, Locale, LocaleFormat, Localized};
+
+    #[test]
+    fn test_filter() {
+        let mut localized = Localized::new(Some("Grass"));
+        for (locale, value) in [
+            ("en-US", "Grass"),
+            ("zh-CN", "草"),
+            ("ja", "草"),
+            ("ko", "풀"),
+        ] {
+            localized.set(Locale::new(locale), value);
+        }
+        let cjk = ["zh", "ja", "ko"].map(Locale::new);
+        let filtered = localized.filter(|l| cjk.contains(&l.language_only()));
+        assert_eq!(filtered.get_default(), Some(&"Grass"));
+        assert_eq!(
+            filtered.locales(),
+            [
+                &Locale::new("ja"),
+                &Locale::new("ko"),
+                &Locale::new("zh-CN")
+            ]
+        );
+        assert_eq!(localized.content_len(), 4);
+        assert!(localized.filter(|_| false).keys().next().is_none());
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("en-us", "en-usa"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("草", ""), 1);
+    }
+
+    #[test]
+    fn test_get_closest() {
+        let mut localized = Localized::new(Some("Grass"));
+        for (locale, value) in [
+            ("en-US", "Grass"),
+            ("en-GB", "Lawn"),
+            ("zh", "草"),
+            ("zh-TW", "草地"),
+        ] {
+            localized.set(Locale::new(locale), value);
+        }
+        let closest = |l| localized.get_closest(&Locale::new(l));
+        assert_eq!(closest("en-GB"), Some(&"Lawn"));
+        // Region typos pick the closest of the same language
+        assert_eq!(closest("en-USA"), Some(&"Grass"));
+        assert_eq!(closest("en-GBR"), Some(&"Lawn"));
+        // The language alone comes before typo tolerance
+        assert_eq!(closest("zh-TWN"), Some(&"草"));
+        // Other languages only get the default
+        assert_eq!(closest("ja-JP"), Some(&"Grass"));
+        assert_eq!(
+            Localized::<&str>::new(None).get_closest(&Locale::new("ja")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut localized = Localized::new(Some(String::from("Grass")));
+        for (locale, value) in [
+            ("en-US", "Grass"),
+            ("zh-CN", "草"),
+            ("ja", ""),
+            ("ko", "풀밭"),
+        ] {
+            localized.set(Locale::new(locale), value.to_owned());
+        }
+        localized.retain_non_empty();
+        assert_eq!(localized.content_len(), 3);
+        assert_eq!(localized.get(&Locale::new("ja")), None);
+
+        // Values may be changed on the way
+        localized.retain(|_, value| {
+            value.push('!');
+            value.chars().count() > 2
+        });
+        assert_eq!(localized.content_len(), 2);
+        assert_eq!(localized.len(), 3);
+        assert_eq!(localized.get(&Locale::new("ko")).unwrap(), "풀밭!");
+        assert_eq!(localized.get(&Locale::new("zh-CN")), None);
+        assert_eq!(localized.get_default().unwrap(), "Grass");
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let mut localized = Localized::new(None);
+        let en = Locale::new("en");
+        assert_eq!(localized.get_or_insert_with(en.clone(), || 1), &1);
+        // Present values are kept
+        *localized.get_or_insert_with(en.clone(), || 2) += 10;
+        assert_eq!(localized.get(&en), Some(&11));
+        assert_eq!(localized.get_or_insert_with(Locale::new("en_US"), || 3), &3);
+        assert_eq!(localized.content_len(), 2);
+
+        assert_eq!(localized.default_or_insert_with(|| 4), &4);
+        assert_eq!(localized.default_or_insert_with(|| 5), &4);
+        assert_eq!(localized.get_default(), Some(&4));
+    }
+
+    #[test]
+    fn test_sorted_entries() {
+        let mut localized = Localized::new(Some("default"));
+        for locale in ["zh-TW", "en-US", "zh_CN", "ja", "en", "pt-BR", "pt"] {
+            localized.set(Locale::new(locale), locale);
+        }
+        let order: Vec<_> = localized
+            .sorted_entries()
+            .into_iter()
+            .map(|(locale, value)| (locale.to_locale(), *value))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("en", "en"),
+                ("en-US", "en-US"),
+                ("ja", "ja"),
+                ("pt", "pt"),
+                ("pt-BR", "pt-BR"),
+                ("zh-CN", "zh_CN"),
+                ("zh-TW", "zh-TW"),
+            ]
+        );
+        let mut locales = vec![
+            Locale::new("zh-TW"),
+            Locale::new("en-US"),
+            Locale::new("en"),
+        ];
+        locales.sort();
+        assert_eq!(
+            locales,
+            [
+                Locale::new("en"),
+                Locale::new("en-US"),
+                Locale::new("zh-TW")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        for (locale, underscore, hyphen, posix) in [
+            ("zh_CN", "zh_CN", "zh-CN", "zh_CN"),
+            ("zh-hant-tw", "zh_Hant_TW", "zh-Hant-TW", "zh_TW"),
+            (
+                "de_DE.ISO-8859-15@euro",
+                "de_DE.ISO-8859-15@euro",
+                "de-DE.ISO-8859-15@euro",
+                "de_DE.ISO-8859-15@euro",
+            ),
+        ] {
+            let locale = Locale::new(locale);
+            assert_eq!(locale.format(LocaleFormat::Underscore), underscore);
+            assert_eq!(locale.format(LocaleFormat::Hyphen), hyphen);
+            assert_eq!(locale.format(LocaleFormat::Posix), posix);
+        }
+    }
+
+    #[test]
+    fn test_to_posix() {
+        assert_eq!(Locale::new("en").to_posix(), "en");
+        assert_eq!(Locale::new("zh-CN").to_posix(), "zh_CN");
+        assert_eq!(Locale::new("zh_cn").to_posix(), "zh_CN");
+        assert_eq!(Locale::new("en_US.UTF-8").to_posix(), "en_US.UTF-8");
+        assert_eq!(Locale::new("ca-ES@valencia").to_posix(), "ca_ES@valencia");
+        assert_eq!(
+            Locale::new("de_DE.ISO-8859-15@euro").to_posix(),
+            "de_DE.ISO-8859-15@euro"
+        );
+        assert_eq!(Locale::new("es-419").to_posix(), "es_419");
+    }
+
+    #[test]
+    fn test_posix_parts() {
+        for (posix, tag, language, region, encoding, modifier) in [
+            (
+                "zh_CN.UTF-8",
+                "zh-CN",
+                "zh",
+                Some("CN"),
+                Some("UTF-8"),
+                None,
+            ),
+            (
+                "en_US.UTF-8@euro",
+                "en-US",
+                "en",
+                Some("US"),
+                Some("UTF-8"),
+                Some("euro"),
+            ),
+            (
+                "ca_ES@valencia",
+                "ca-ES",
+                "ca",
+                Some("ES"),
+                None,
+                Some("valencia"),
+            ),
+            (
+                "de_DE.ISO-8859-15",
+                "de-DE",
+                "de",
+                Some("DE"),
+                Some("ISO-8859-15"),
+                None,
+            ),
+            ("ja.eucJP", "ja", "ja", None, Some("eucJP"), None),
+            (
+                "sr_RS@latin",
+                "sr-RS",
+                "sr",
+                Some("RS"),
+                None,
+                Some("latin"),
+            ),
+        ] {
+            let locale = Locale::new(posix);
+            assert_eq!(locale.to_string(), tag);
+            assert_eq!(format!("{:#}", locale), posix);
+            assert_eq!(locale.language(), language);
+            assert_eq!(locale.region(), region);
+            assert_eq!(locale.encoding(), encoding);
+            assert_eq!(locale.modifier(), modifier);
+        }
+        // The encoding stays part of the identity of the locale
+        assert_ne!(Locale::new("zh_CN.UTF-8"), Locale::new("zh_CN"));
+        assert_eq!(
+            Locale::new("zh_CN.UTF-8").language_only(),
+            Locale::new("zh")
+        );
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        for valid in [
+            "en",
+            "fil",
+            "zh-CN",
+            "zh_cn",
+            "es-419",
+            "zh-Hant-TW",
+            "en_US.UTF-8",
+            "ca-ES@valencia",
+        ] {
+            assert!(
+                Locale::parse_strict(valid).is_ok(),
+                "{} should be valid",
+                valid
+            );
+        }
+        for invalid in [
+            "", "!!!", "e", "engl", "en-", "en-USA", "en-1234", "zh-CN-x", "z1-CN",
+        ] {
+            assert!(
+                Locale::parse_strict(invalid).is_err(),
+                "{} should be invalid",
+                invalid
+            );
+        }
+        // The lenient constructors still take anything
+        assert_eq!("!!!".parse::<Locale>().unwrap().to_locale(), "!!!");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(Locale::new("en_us").to_locale(), "en-US");
+        assert_eq!(Locale::new("ZH-hant-tw").to_locale(), "zh-Hant-TW");
+        assert_eq!(Locale::new("es_419").to_locale(), "es-419");
+        assert_eq!(Locale::new("en_US.UTF-8").to_locale(), "en-US.UTF-8");
+        assert_eq!(Locale::new("ca_es@valencia").to_locale(), "ca-ES@valencia");
+        assert_eq!(Locale::new("en-US"), Locale::new("en_US"));
+
+        let mut localized = Localized::new(None);
+        localized.set(Locale::new("en-US"), "Grass");
+        localized.set("en_US".parse().unwrap(), "Kusa");
+        assert_eq!(localized.len(), 1);
+        assert_eq!(localized.get(&Locale::new("en-us")), Some(&"Kusa"));
+    }
+
+    #[test]
+    fn test_best_default() {
+        let mut localized = Localized::new(None);
+        assert_eq!(localized.best_default(), None);
+        localized.set(Locale::new("zh-CN"), "草");
+        localized.set(Locale::new("ja-JP"), "草原");
+        assert_eq!(localized.best_default(), Some(&"草原"));
+        localized.set(Locale::new("en-US"), "Grass");
+        assert_eq!(localized.best_default(), Some(&"Grass"));
+        localized.set(Locale::new("en"), "Lawn");
+        assert_eq!(localized.best_default(), Some(&"Lawn"));
+        localized.merge(Localized::new(Some("Kusa")));
+        assert_eq!(localized.best_default(), Some(&"Kusa"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let localized = |default: Option<&'static str>, content: &[(&str, &'static str)]| {
+            let mut ret = Localized::new(default);
+            for (locale, value) in content {
+                ret.set(Locale::new(locale), *value);
+            }
+            ret
+        };
+        let base = localized(Some("Kusa"), &[("en-US", "Grass"), ("zh-CN", "草")]);
+
+        // The default is only replaced if the other side has one
+        let merged = base.clone().merged(localized(None, &[]));
+        assert_eq!(merged, base);
+        let merged = base.clone().merged(localized(Some("Grass"), &[]));
+        assert_eq!(merged.get_default(), Some(&"Grass"));
+
+        // Translations for the same locale are overwritten
+        let merged = base.clone().merged(localized(None, &[("zh-CN", "小草")]));
+        assert_eq!(merged.get(&Locale::new("zh-CN")), Some(&"小草"));
+        assert_eq!(merged.get(&Locale::new("en-US")), Some(&"Grass"));
+
+        // Disjoint translations are combined
+        let mut merged = base.clone();
+        merged.merge(localized(None, &[("ja-JP", "草")]));
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged.get_default(), Some(&"Kusa"));
+    }
+
+    #[test]
+    fn test_keys_values() {
+        let mut localized = Localized::new(Some("Kusa"));
+        localized.set(Locale::new("zh-CN"), "草");
+        localized.set(Locale::new("en-US"), "Grass");
+        localized.set(Locale::new("ja-JP"), "草原");
+        // Sorted by locale, without the default
+        assert_eq!(
+            localized.locales(),
+            [
+                &Locale::new("en-US"),
+                &Locale::new("ja-JP"),
+                &Locale::new("zh-CN")
+            ]
+        );
+        assert_eq!(
+            localized.values().collect::<Vec<_>>(),
+            [&"Grass", &"草原", &"草"]
+        );
+        assert_eq!(localized.keys().count(), localized.content_len());
+    }
+
+    #[test]
+    fn test_und() {
+        let und = Locale::und();
+        assert!(und.is_und());
+        assert_eq!(und.to_string(), "und");
+        assert_eq!(und.to_string().parse::<Locale>().unwrap(), und);
+        assert!("UND".parse::<Locale>().unwrap().is_und());
+        assert!(!Locale::new("en").is_und());
+    }
+
+    #[test]
+    fn test_unspecified() {
+        for unspecified in ["", "C", "c", "POSIX", "posix", "C.UTF-8", "POSIX@euro"] {
+            assert!(
+                Locale::new(unspecified).is_und(),
+                "{} should be und",
+                unspecified
+            );
+            assert!(unspecified.parse::<Locale>().unwrap().is_und());
+            assert!(Locale::new_with_aliases(unspecified).is_und());
+            assert!(
+                Locale::parse_strict(unspecified).is_err(),
+                "{} should be invalid",
+                unspecified
+            );
+        }
+        // Only whole tags are unspecified
+        assert_eq!(Locale::new("ca").to_locale(), "ca");
+        assert_eq!(Locale::new("cy-GB").to_locale(), "cy-GB");
+    }
+
+    #[test]
+    fn test_language_only() {
+        assert_eq!(Locale::new("en_US").language_only(), Locale::new("en"));
+        assert_eq!(Locale::new("zh-Hant-TW").language_only(), Locale::new("zh"));
+        assert_eq!(
+            Locale::new("de_DE.UTF-8@euro").language_only(),
+            Locale::new("de")
+        );
+        assert_eq!(Locale::new("fil").language_only(), Locale::new("fil"));
+    }
+
+    #[test]
+    fn test_aliases() {
+        for mixed in ["EN_us", "En-Us", "en_US", "en-us"] {
+            assert_eq!(Locale::new_with_aliases(mixed).to_locale(), "en-US");
+        }
+        assert_eq!(Locale::new_with_aliases("pt_br"), Locale::new("pt-BR"));
+        assert_eq!(Locale::new_with_aliases("iw").to_locale(), "he");
+        assert_eq!(Locale::new_with_aliases("IW_il").to_locale(), "he-IL");
+        assert_eq!(
+            Locale::new_with_aliases("in_ID.UTF-8").to_locale(),
+            "id-ID.UTF-8"
+        );
+        // Only the language is looked up
+        assert_eq!(Locale::new_with_aliases("inh").to_locale(), "inh");
+        assert_eq!(Locale::new("iw").to_locale(), "iw");
+    }
+
+    #[test]
+    fn test_content_len() {
+        let mut localized = Localized::new(Some("Kusa"));
+        localized.set(Locale::new("en-US"), "Grass");
+        localized.set(Locale::new("zh-CN"), "草");
+        assert!(localized.has_default());
+        assert_eq!(localized.len(), 3);
+        assert_eq!(localized.content_len(), 2);
+
+        let localized = Localized::<&str>::new(None);
+        assert!(!localized.has_default());
+        assert_eq!(localized.len(), localized.content_len());
+    }
+}
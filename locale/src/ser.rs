@@ -0,0 +1,74 @@
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+pub use crate::Localized;
+
+impl<T> Serialize for Localized<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        if let Some(default) = &self.default {
+            map.serialize_entry("default", default)?;
+        }
+        // The entries are sorted, so the output is stable
+        for (locale, value) in &self.content {
+            map.serialize_entry(locale.to_locale(), value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+
+    use super::Localized;
+    use crate::Locale;
+
+    #[test]
+    fn test_ser() {
+        let example = r#"
+        zh-CN = "草"
+        default = "Kusa"
+        en-US = "Grass"
+        "#;
+
+        let localized = toml::from_str::<Localized<String>>(example).unwrap();
+        assert_eq!(
+            serde_json::to_string(&localized).unwrap(),
+            r#"{"default":"Kusa","en-US":"Grass","zh-CN":"草"}"#
+        );
+        let roundtrip = toml::from_str::<Localized<String>>(&toml::to_string(&localized).unwrap());
+        assert_eq!(roundtrip.unwrap(), localized);
+    }
+
+    #[test]
+    fn test_skip_empty() {
+        #[derive(Serialize)]
+        struct Wallpaper {
+            id: &'static str,
+            #[serde(skip_serializing_if = "Localized::is_empty")]
+            title: Localized<String>,
+        }
+
+        let mut wallpaper = Wallpaper {
+            id: "Kusa",
+            title: Localized::new(None),
+        };
+        assert_eq!(
+            serde_json::to_string(&wallpaper).unwrap(),
+            r#"{"id":"Kusa"}"#
+        );
+        assert_eq!(toml::to_string(&wallpaper).unwrap(), "id = \"Kusa\"\n");
+        // Translations alone are not empty
+        wallpaper.title.set(Locale::new("zh-CN"), "草".into());
+        assert_eq!(
+            serde_json::to_string(&wallpaper).unwrap(),
+            r#"{"id":"Kusa","title":{"zh-CN":"草"}}"#
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use hex_color::HexColor;
+use serde::{Deserialize, Deserializer};
+
+/// CSS named colors, sorted by name for binary search.
+static NAMED_COLORS: &[(&str, HexColor)] = &[
+    ("aliceblue", HexColor::rgb(240, 248, 255)),
+    ("antiquewhite", HexColor::rgb(250, 235, 215)),
+    ("aqua", HexColor::rgb(0, 255, 255)),
+    ("aquamarine", HexColor::rgb(127, 255, 212)),
+    ("azure", HexColor::rgb(240, 255, 255)),
+    ("beige", HexColor::rgb(245, 245, 220)),
+    ("bisque", HexColor::rgb(255, 228, 196)),
+    ("black", HexColor::rgb(0, 0, 0)),
+    ("blanchedalmond", HexColor::rgb(255, 235, 205)),
+    ("blue", HexColor::rgb(0, 0, 255)),
+    ("blueviolet", HexColor::rgb(138, 43, 226)),
+    ("brown", HexColor::rgb(165, 42, 42)),
+    ("burlywood", HexColor::rgb(222, 184, 135)),
+    ("cadetblue", HexColor::rgb(95, 158, 160)),
+    ("chartreuse", HexColor::rgb(127, 255, 0)),
+    ("chocolate", HexColor::rgb(210, 105, 30)),
+    ("coral", HexColor::rgb(255, 127, 80)),
+    ("cornflowerblue", HexColor::rgb(100, 149, 237)),
+    ("cornsilk", HexColor::rgb(255, 248, 220)),
+    ("crimson", HexColor::rgb(220, 20, 60)),
+    ("cyan", HexColor::rgb(0, 255, 255)),
+    ("darkblue", HexColor::rgb(0, 0, 139)),
+    ("darkcyan", HexColor::rgb(0, 139, 139)),
+    ("darkgoldenrod", HexColor::rgb(184, 134, 11)),
+    ("darkgray", HexColor::rgb(169, 169, 169)),
+    ("darkgreen", HexColor::rgb(0, 100, 0)),
+    ("darkgrey", HexColor::rgb(169, 169, 169)),
+    ("darkkhaki", HexColor::rgb(189, 183, 107)),
+    ("darkmagenta", HexColor::rgb(139, 0, 139)),
+    ("darkolivegreen", HexColor::rgb(85, 107, 47)),
+    ("darkorange", HexColor::rgb(255, 140, 0)),
+    ("darkorchid", HexColor::rgb(153, 50, 204)),
+    ("darkred", HexColor::rgb(139, 0, 0)),
+    ("darksalmon", HexColor::rgb(233, 150, 122)),
+    ("darkseagreen", HexColor::rgb(143, 188, 143)),
+    ("darkslateblue", HexColor::rgb(72, 61, 139)),
+    ("darkslategray", HexColor::rgb(47, 79, 79)),
+    ("darkslategrey", HexColor::rgb(47, 79, 79)),
+    ("darkturquoise", HexColor::rgb(0, 206, 209)),
+    ("darkviolet", HexColor::rgb(148, 0, 211)),
+    ("deeppink", HexColor::rgb(255, 20, 147)),
+    ("deepskyblue", HexColor::rgb(0, 191, 255)),
+    ("dimgray", HexColor::rgb(105, 105, 105)),
+    ("dimgrey", HexColor::rgb(105, 105, 105)),
+    ("dodgerblue", HexColor::rgb(30, 144, 255)),
+    ("firebrick", HexColor::rgb(178, 34, 34)),
+    ("floralwhite", HexColor::rgb(255, 250, 240)),
+    ("forestgreen", HexColor::rgb(34, 139, 34)),
+    ("fuchsia", HexColor::rgb(255, 0, 255)),
+    ("gainsboro", HexColor::rgb(220, 220, 220)),
+    ("ghostwhite", HexColor::rgb(248, 248, 255)),
+    ("gold", HexColor::rgb(255, 215, 0)),
+    ("goldenrod", HexColor::rgb(218, 165, 32)),
+    ("gray", HexColor::rgb(128, 128, 128)),
+    ("green", HexColor::rgb(0, 128, 0)),
+    ("greenyellow", HexColor::rgb(173, 255, 47)),
+    ("grey", HexColor::rgb(128, 128, 128)),
+    ("honeydew", HexColor::rgb(240, 255, 240)),
+    ("hotpink", HexColor::rgb(255, 105, 180)),
+    ("indianred", HexColor::rgb(205, 92, 92)),
+    ("indigo", HexColor::rgb(75, 0, 130)),
+    ("ivory", HexColor::rgb(255, 255, 240)),
+    ("khaki", HexColor::rgb(240, 230, 140)),
+    ("lavender", HexColor::rgb(230, 230, 250)),
+    ("lavenderblush", HexColor::rgb(255, 240, 245)),
+    ("lawngreen", HexColor::rgb(124, 252, 0)),
+    ("lemonchiffon", HexColor::rgb(255, 250, 205)),
+    ("lightblue", HexColor::rgb(173, 216, 230)),
+    ("lightcoral", HexColor::rgb(240, 128, 128)),
+    ("lightcyan", HexColor::rgb(224, 255, 255)),
+    ("lightgoldenrodyellow", HexColor::rgb(250, 250, 210)),
+    ("lightgray", HexColor::rgb(211, 211, 211)),
+    ("lightgreen", HexColor::rgb(144, 238, 144)),
+    ("lightgrey", HexColor::rgb(211, 211, 211)),
+    ("lightpink", HexColor::rgb(255, 182, 193)),
+    ("lightsalmon", HexColor::rgb(255, 160, 122)),
+    ("lightseagreen", HexColor::rgb(32, 178, 170)),
+    ("lightskyblue", HexColor::rgb(135, 206, 250)),
+    ("lightslategray", HexColor::rgb(119, 136, 153)),
+    ("lightslategrey", HexColor::rgb(119, 136, 153)),
+    ("lightsteelblue", HexColor::rgb(176, 196, 222)),
+    ("lightyellow", HexColor::rgb(255, 255, 224)),
+    ("lime", HexColor::rgb(0, 255, 0)),
+    ("limegreen", HexColor::rgb(50, 205, 50)),
+    ("linen", HexColor::rgb(250, 240, 230)),
+    ("magenta", HexColor::rgb(255, 0, 255)),
+    ("maroon", HexColor::rgb(128, 0, 0)),
+    ("mediumaquamarine", HexColor::rgb(102, 205, 170)),
+    ("mediumblue", HexColor::rgb(0, 0, 205)),
+    ("mediumorchid", HexColor::rgb(186, 85, 211)),
+    ("mediumpurple", HexColor::rgb(147, 112, 219)),
+    ("mediumseagreen", HexColor::rgb(60, 179, 113)),
+    ("mediumslateblue", HexColor::rgb(123, 104, 238)),
+    ("mediumspringgreen", HexColor::rgb(0, 250, 154)),
+    ("mediumturquoise", HexColor::rgb(72, 209, 204)),
+    ("mediumvioletred", HexColor::rgb(199, 21, 133)),
+    ("midnightblue", HexColor::rgb(25, 25, 112)),
+    ("mintcream", HexColor::rgb(245, 255, 250)),
+    ("mistyrose", HexColor::rgb(255, 228, 225)),
+    ("moccasin", HexColor::rgb(255, 228, 181)),
+    ("navajowhite", HexColor::rgb(255, 222, 173)),
+    ("navy", HexColor::rgb(0, 0, 128)),
+    ("oldlace", HexColor::rgb(253, 245, 230)),
+    ("olive", HexColor::rgb(128, 128, 0)),
+    ("olivedrab", HexColor::rgb(107, 142, 35)),
+    ("orange", HexColor::rgb(255, 165, 0)),
+    ("orangered", HexColor::rgb(255, 69, 0)),
+    ("orchid", HexColor::rgb(218, 112, 214)),
+    ("palegoldenrod", HexColor::rgb(238, 232, 170)),
+    ("palegreen", HexColor::rgb(152, 251, 152)),
+    ("paleturquoise", HexColor::rgb(175, 238, 238)),
+    ("palevioletred", HexColor::rgb(219, 112, 147)),
+    ("papayawhip", HexColor::rgb(255, 239, 213)),
+    ("peachpuff", HexColor::rgb(255, 218, 185)),
+    ("peru", HexColor::rgb(205, 133, 63)),
+    ("pink", HexColor::rgb(255, 192, 203)),
+    ("plum", HexColor::rgb(221, 160, 221)),
+    ("powderblue", HexColor::rgb(176, 224, 230)),
+    ("purple", HexColor::rgb(128, 0, 128)),
+    ("rebeccapurple", HexColor::rgb(102, 51, 153)),
+    ("red", HexColor::rgb(255, 0, 0)),
+    ("rosybrown", HexColor::rgb(188, 143, 143)),
+    ("royalblue", HexColor::rgb(65, 105, 225)),
+    ("saddlebrown", HexColor::rgb(139, 69, 19)),
+    ("salmon", HexColor::rgb(250, 128, 114)),
+    ("sandybrown", HexColor::rgb(244, 164, 96)),
+    ("seagreen", HexColor::rgb(46, 139, 87)),
+    ("seashell", HexColor::rgb(255, 245, 238)),
+    ("sienna", HexColor::rgb(160, 82, 45)),
+    ("silver", HexColor::rgb(192, 192, 192)),
+    ("skyblue", HexColor::rgb(135, 206, 235)),
+    ("slateblue", HexColor::rgb(106, 90, 205)),
+    ("slategray", HexColor::rgb(112, 128, 144)),
+    ("slategrey", HexColor::rgb(112, 128, 144)),
+    ("snow", HexColor::rgb(255, 250, 250)),
+    ("springgreen", HexColor::rgb(0, 255, 127)),
+    ("steelblue", HexColor::rgb(70, 130, 180)),
+    ("tan", HexColor::rgb(210, 180, 140)),
+    ("teal", HexColor::rgb(0, 128, 128)),
+    ("thistle", HexColor::rgb(216, 191, 216)),
+    ("tomato", HexColor::rgb(255, 99, 71)),
+    ("turquoise", HexColor::rgb(64, 224, 208)),
+    ("violet", HexColor::rgb(238, 130, 238)),
+    ("wheat", HexColor::rgb(245, 222, 179)),
+    ("white", HexColor::rgb(255, 255, 255)),
+    ("whitesmoke", HexColor::rgb(245, 245, 245)),
+    ("yellow", HexColor::rgb(255, 255, 0)),
+    ("yellowgreen", HexColor::rgb(154, 205, 50)),
+];
+
+/// Parses a hex color like `#5789CA`, or a CSS named color like `navy`.
+pub fn parse_color(s: &str) -> Option<HexColor> {
+    if s.starts_with('#') {
+        return HexColor::parse(s).ok();
+    }
+    let name = s.to_ascii_lowercase();
+    NAMED_COLORS
+        .binary_search_by(|(n, _)| n.cmp(&name.as_str()))
+        .ok()
+        .map(|i| NAMED_COLORS[i].1)
+}
+
+/// Deserializes an optional color with [`parse_color`].
+pub fn deserialize_opt<'de, D>(deserializer: D) -> Result<Option<HexColor>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(s) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    parse_color(&s)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid color {:?}", s)))
+}
+
+#[cfg(test)]
+mod test {
+    use hex_color::HexColor;
+
+    use super::{parse_color, NAMED_COLORS};
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(
+            parse_color("#5789CA"),
+            Some(HexColor::rgb(0x57, 0x89, 0xCA))
+        );
+        assert_eq!(parse_color("#fff"), Some(HexColor::WHITE));
+        assert_eq!(parse_color("navy"), Some(HexColor::rgb(0, 0, 128)));
+        assert_eq!(
+            parse_color("RebeccaPurple"),
+            Some(HexColor::rgb(0x66, 0x33, 0x99))
+        );
+        assert_eq!(parse_color("notacolor"), None);
+        assert_eq!(parse_color("#12345"), None);
+        assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
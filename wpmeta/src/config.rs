@@ -0,0 +1,97 @@
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use eyre::{bail, eyre, Result};
+use log::debug;
+use toml::{Table, Value};
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub static CONFIG_FILE: &str = "wpmeta.toml";
+
+/// Arguments only the command line may give.
+static CLI_ONLY: &[&str] = &["src", "dst"];
+
+/// Directory of the user-wide config file, `$XDG_CONFIG_HOME/wpmeta`.
+pub fn config_home() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("wpmeta"))
+}
+
+fn read_table(dir: &Path) -> Result<Table> {
+    let path = dir.join(CONFIG_FILE);
+    if !path.is_file() {
+        return Ok(Table::new());
+    }
+    debug!("reading config from {}", path.display());
+    let content = fs::read_to_string(&path)?;
+    content
+        .parse()
+        .map_err(|e| eyre!("invalid config {}: {}", path.display(), e))
+}
+
+/// Loads the config of a run on `src`. Entries in the config of the source
+/// tree take precedence over those in `home`.
+pub fn load(src: &Path, home: Option<&Path>) -> Result<Table> {
+    let mut ret = match home {
+        Some(home) => read_table(home)?,
+        None => Table::new(),
+    };
+    ret.extend(read_table(src)?);
+    Ok(ret)
+}
+
+/// Turns the entries of `config` into arguments of `cmd`, skipping those
+/// already given on the command line in `matches`. Keys are the option
+/// names in snake case, e.g. `preview_resolution_limit`.
+pub fn to_args(cmd: &Command, matches: &ArgMatches, config: &Table) -> Result<Vec<OsString>> {
+    let mut ret = Vec::new();
+    for (key, value) in config {
+        let arg = cmd
+            .get_arguments()
+            .find(|a| a.get_id() == key && !CLI_ONLY.contains(&key.as_str()))
+            .ok_or_else(|| eyre!("unknown config option {}", key))?;
+        let long = arg.get_long().expect("Generation options have long names");
+        if matches.value_source(key) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match value {
+            // Flags that are on by default take their value explicitly
+            Value::Boolean(b) if arg.get_action().takes_values() => vec![b.to_string()],
+            Value::Boolean(true) => {
+                ret.push(format!("--{}", long).into());
+                continue;
+            }
+            Value::Boolean(false) => continue,
+            Value::String(s) => vec![s.clone()],
+            Value::Integer(i) => vec![i.to_string()],
+            Value::Float(f) => vec![f.to_string()],
+            Value::Array(values) => {
+                let values = values
+                    .iter()
+                    .map(|v| match v {
+                        Value::String(s) => Ok(s.clone()),
+                        _ => Err(eyre!("config option {} takes a list of strings", key)),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                // Options without a delimiter are repeated instead
+                match arg.get_value_delimiter() {
+                    Some(delimiter) => vec![values.join(&delimiter.to_string())],
+                    None => values,
+                }
+            }
+            _ => bail!("unsupported value for config option {}", key),
+        };
+        ret.extend(
+            values
+                .into_iter()
+                .map(|v| format!("--{}={}", long, v).into()),
+        );
+    }
+    Ok(ret)
+}
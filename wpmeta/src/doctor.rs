@@ -0,0 +1,153 @@
+use image::ImageFormat;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use crate::walk;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub ok: bool,
+    pub message: String,
+}
+
+impl Finding {
+    pub(crate) fn ok<S: Into<String>>(message: S) -> Self {
+        Self {
+            ok: true,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn fail<S: Into<String>>(message: S) -> Self {
+        Self {
+            ok: false,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.ok { " OK " } else { "FAIL" };
+        write!(f, "[{}] {}", mark, self.message)
+    }
+}
+
+fn check_src(src: &Path) -> Finding {
+    if !src.exists() {
+        return Finding::fail(format!("source {} does not exist", src.display()));
+    }
+    if !src.is_dir() {
+        return Finding::fail(format!("source {} is not a directory", src.display()));
+    }
+    match fs::read_dir(src) {
+        Ok(_) => Finding::ok(format!("source {} is readable", src.display())),
+        Err(e) => Finding::fail(format!("source {} is not readable: {}", src.display(), e)),
+    }
+}
+
+fn check_formats(src: &Path) -> Finding {
+    let metas = match walk::walk(src, None) {
+        Ok(metas) => metas,
+        Err(e) => return Finding::fail(format!("failed to read manifests: {}", e)),
+    };
+    let mut unsupported = Vec::new();
+    let mut count = 0;
+    for meta in &metas {
+        let base = meta.base().unwrap_or(src);
+        for wallpaper in meta.wallpapers().into_iter().flatten() {
+            for src in [Some(wallpaper.src()), wallpaper.dark_src()]
+                .into_iter()
+                .flatten()
+                .chain(wallpaper.resolution_srcs())
+            {
+                count += 1;
+                let path = base.join(src);
+                let supported = ImageFormat::from_path(&path)
+                    .map(|f| f.can_read())
+                    .unwrap_or(false);
+                if !supported {
+                    unsupported.push(path.display().to_string());
+                }
+            }
+        }
+    }
+    if unsupported.is_empty() {
+        Finding::ok(format!("all {} source images can be decoded", count))
+    } else {
+        Finding::fail(format!(
+            "unsupported image formats: {}",
+            unsupported.join(", ")
+        ))
+    }
+}
+
+/// Checks writing and symlinking in the closest existing ancestor of `dst`.
+fn check_dst(dst: &Path) -> Vec<Finding> {
+    let Some(dir) = dst.ancestors().find(|p| p.is_dir()) else {
+        return vec![Finding::fail(format!(
+            "destination {} has no existing parent",
+            dst.display()
+        ))];
+    };
+    let probe = dir.join(format!(".wpmeta-doctor-{}", std::process::id()));
+    if let Err(e) = File::create(&probe) {
+        return vec![Finding::fail(format!(
+            "destination {} is not writable: {}",
+            dir.display(),
+            e
+        ))];
+    }
+    let mut ret = vec![Finding::ok(format!(
+        "destination {} is writable",
+        dir.display()
+    ))];
+    let link = probe.with_extension("link");
+    ret.push(match symlink(&probe, &link) {
+        Ok(_) => Finding::ok("symlinks are supported for MATE manifests"),
+        Err(e) => Finding::fail(format!("symlinks are not supported: {}", e)),
+    });
+    let _ = fs::remove_file(link);
+    let _ = fs::remove_file(probe);
+    ret
+}
+
+pub fn doctor(src: &Path, dst: &Path) -> Vec<Finding> {
+    let mut ret = vec![check_src(src)];
+    if ret[0].ok {
+        ret.push(check_formats(src));
+    }
+    ret.append(&mut check_dst(dst));
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::doctor;
+    use crate::test::{dummy_tree, DUMMY_TREE_META};
+
+    #[test]
+    fn test_missing_src() {
+        let dst = tempfile::tempdir().unwrap();
+        let src = dst.path().join("nonexistent");
+        let findings = doctor(&src, dst.path());
+        assert!(!findings[0].ok);
+        assert_eq!(
+            findings[0].to_string(),
+            format!("[FAIL] source {} does not exist", src.display())
+        );
+        assert!(findings[1..].iter().all(|f| f.ok));
+    }
+
+    #[test]
+    fn test_ok() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let findings = doctor(src.path(), &dst.path().join("pkg"));
+        assert_eq!(findings.len(), 4);
+        assert!(findings.iter().all(|f| f.ok));
+    }
+}
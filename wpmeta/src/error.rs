@@ -0,0 +1,76 @@
+use thiserror::Error;
+
+use std::io;
+use std::path::PathBuf;
+
+/// Errors of the generation steps that callers may want to tell apart.
+#[derive(Debug, Error)]
+pub enum WpmetaError {
+    #[error("path {0:?} does not exist")]
+    MissingFile(PathBuf),
+    #[error("path {0:?} is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("failed to read {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("cannot detect the image format of {0:?}")]
+    UnknownFormat(PathBuf),
+    #[error("failed to decode {path:?}")]
+    ImageDecode {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("image {0:?} declares a zero width or height")]
+    EmptyImage(PathBuf),
+    #[error("invalid resolution {input:?}, {reason}")]
+    InvalidResolution { input: String, reason: String },
+    #[error("invalid aspect ratio {input:?}, {reason}")]
+    InvalidAspectRatio { input: String, reason: String },
+    #[error("invalid id {id:?}, {reason}")]
+    InvalidId { id: String, reason: &'static str },
+    #[error("{id}: image is {width}x{height}, below the minimum of {min_width}x{min_height}")]
+    ResolutionTooLow {
+        id: String,
+        width: u32,
+        height: u32,
+        min_width: u32,
+        min_height: u32,
+    },
+    #[error("duplicate wallpaper id {id} in {} and {}", .first.display(), .second.display())]
+    DuplicateId {
+        id: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    #[error("{first} and {second} both install to {path:?}")]
+    DuplicatePackage {
+        path: PathBuf,
+        first: String,
+        second: String,
+    },
+    #[error("{wallpaper}: unknown author {email}")]
+    NoAuthor { wallpaper: String, email: String },
+    #[error("{id}: no {kind} manifest was rendered")]
+    MissingManifest { id: String, kind: &'static str },
+    #[error("no wallpapers to generate")]
+    NoWallpapers,
+    #[error("no metadata.toml found under {0:?}")]
+    NoMetadata(PathBuf),
+    #[error("prefix {0:?} must be relative and stay inside the destination")]
+    InvalidPrefix(PathBuf),
+}
+
+impl WpmetaError {
+    /// Wraps an error opening `path`, telling missing files apart.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            io::ErrorKind::NotFound => Self::MissingFile(path),
+            _ => Self::Io { path, source },
+        }
+    }
+}
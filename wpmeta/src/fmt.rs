@@ -0,0 +1,97 @@
+use eyre::Result;
+use log::info;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::walk::{parse_meta, METADATA_FILE};
+
+/// Collects every manifest under `dir`, sorted by path.
+fn manifests(dir: &Path, ret: &mut Vec<PathBuf>) -> Result<()> {
+    let meta_file = dir.join(METADATA_FILE);
+    if meta_file.is_file() {
+        ret.push(meta_file);
+    }
+    let mut children = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            children.push(entry.path());
+        }
+    }
+    children.sort();
+    for child in children {
+        manifests(&child, ret)?;
+    }
+    Ok(())
+}
+
+/// Renders `content` in the canonical layout.
+pub fn format(content: &str) -> Result<String> {
+    let mut meta = parse_meta(content)?;
+    meta.normalize();
+    Ok(toml::to_string(&meta)?)
+}
+
+/// Rewrites every manifest under `dir` in the canonical layout, returning
+/// those that changed. With `check`, nothing is written. Comments are not
+/// kept.
+pub fn fmt(dir: &Path, check: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    manifests(dir, &mut files)?;
+    let mut ret = Vec::new();
+    for file in files {
+        let content = fs::read_to_string(&file)?;
+        let formatted = format(&content)?;
+        if formatted == content {
+            continue;
+        }
+        if !check {
+            info!("formatting {}", file.display());
+            fs::write(&file, formatted)?;
+        }
+        ret.push(file);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::fmt;
+
+    static UNFORMATTED: &str = r##"
+    [[authors]]
+    name.zh-CN = "野兽先辈"
+    name.default = "Yajuu Senpai"
+    email = "yajuu.senpai@example.com"
+
+    [[wallpapers]]
+    id = "Kusa"
+    title.en-US = "Grass"
+    title.default = "Kusa"
+    license = "GPL-2.0+"
+    path = "kusa.png"
+    primary_color = "#023c88"
+    "##;
+
+    #[test]
+    fn test_fmt() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        let file = nested.join("metadata.toml");
+        fs::write(&file, UNFORMATTED).unwrap();
+
+        assert_eq!(fmt(dir.path(), true).unwrap(), vec![file.clone()]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), UNFORMATTED);
+        assert_eq!(fmt(dir.path(), false).unwrap(), vec![file.clone()]);
+        let formatted = fs::read_to_string(&file).unwrap();
+        assert!(formatted.contains("license = \"GPL-2.0-or-later\"\n"));
+        assert!(formatted.contains("primary_color = \"#023C88\"\n"));
+        assert!(formatted.contains("default = \"Kusa\"\nen-US = \"Grass\"\n"));
+        // Formatting is idempotent
+        assert!(fmt(dir.path(), true).unwrap().is_empty());
+    }
+}
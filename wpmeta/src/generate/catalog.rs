@@ -0,0 +1,111 @@
+use eyre::Result;
+use hex_color::HexColor;
+use serde::Serialize;
+
+use locale::Localized;
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::WpmetaError;
+use crate::meta::{Author, Category, Metadata, Wallpaper};
+
+/// A wallpaper in the catalog. Colors keep their alpha, written as
+/// `#RRGGBBAA` if not opaque.
+#[derive(Clone, Debug, Serialize)]
+pub struct CatalogEntry {
+    id: String,
+    title: Localized<String>,
+    authors: Vec<Author>,
+    license: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<Category>,
+    primary_color: HexColor,
+    secondary_color: HexColor,
+    image: PathBuf,
+    preview: Option<PathBuf>,
+}
+
+/// Catalog of every wallpaper in a run, shared between the parallel
+/// generation tasks.
+#[derive(Debug, Default)]
+pub struct WallpaperCatalog {
+    wallpapers: Mutex<Vec<CatalogEntry>>,
+}
+
+impl CatalogEntry {
+    pub fn new(
+        metadata: &Metadata,
+        wallpaper: &Wallpaper,
+        base: &Path,
+        prefix: &Path,
+        preview: Option<PathBuf>,
+    ) -> Result<Self, WpmetaError> {
+        let (primary_color, secondary_color) = wallpaper.colors();
+        Ok(Self {
+            id: wallpaper.id().to_owned(),
+            title: wallpaper.titles().clone(),
+            // Unknown authors already fail rendering the KDE metadata
+            authors: metadata
+                .authors_of(wallpaper)
+                .map(|a| a.into_iter().cloned().collect())
+                .unwrap_or_default(),
+            license: wallpaper.license().to_owned(),
+            category: wallpaper.category(),
+            primary_color,
+            secondary_color,
+            image: wallpaper.target(base, prefix)?,
+            preview,
+        })
+    }
+}
+
+impl WallpaperCatalog {
+    pub fn push(&self, entry: CatalogEntry) {
+        self.wallpapers
+            .lock()
+            .expect("Wallpaper catalog lock poisoned")
+            .push(entry);
+    }
+
+    /// Renders the catalog sorted by wallpaper id.
+    pub fn to_json(&self) -> Result<String> {
+        let mut wallpapers = self
+            .wallpapers
+            .lock()
+            .expect("Wallpaper catalog lock poisoned");
+        wallpapers.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(serde_json::to_string_pretty(&*wallpapers)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{CatalogEntry, WallpaperCatalog};
+    use crate::meta::Metadata;
+
+    #[test]
+    fn test_alpha() {
+        let meta = format!(
+            "{}primary_color = \"#023C8880\"\nsecondary_color = \"#5789CA\"\n",
+            crate::meta::test::DUMMY_META
+        );
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let wallpaper = &meta.wallpapers().unwrap()[0];
+        let catalog = WallpaperCatalog::default();
+        let entry = CatalogEntry::new(
+            &meta,
+            wallpaper,
+            Path::new("."),
+            Path::new("usr/share"),
+            None,
+        )
+        .unwrap();
+        catalog.push(entry);
+        let json: serde_json::Value = serde_json::from_str(&catalog.to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["primary_color"], "#023C8880");
+        assert_eq!(json[0]["secondary_color"], "#5789CA");
+    }
+}
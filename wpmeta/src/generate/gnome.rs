@@ -1,22 +1,26 @@
-use eyre::{eyre, Result};
+use eyre::{bail, Result};
 use hex_color::HexColor;
+use log::warn;
 use serde::Serialize;
 use tinytemplate::TinyTemplate;
 
-use locale::{Locale, Localized};
+use locale::{LocaleFormat, Localized};
 
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::meta::{ColorShadingType, Metadata, PictureOptions, Wallpaper};
+use crate::error::WpmetaError;
+use crate::meta::{Author, Metadata, Wallpaper};
 
 static GNOME_WP_LIST_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE wallpapers SYSTEM "gnome-wp-list.dtd">
 <wallpapers>
-    <wallpaper deleted="false">{{ if default_name }}
+    <wallpaper deleted="false">{{ for author in authors }}
+    <!-- Author: { author | unescaped } -->{{ endfor }}{{ if default_name }}
     <name>{ default_name }</name>{{ endif }}{{ for name in names }}
     <name xml:lang="{ name.locale }">{ name.name }</name>{{ endfor }}
-    <filename>/{ filename }</filename>
+    <filename>{ filename }</filename>{{ if filename_dark }}
+    <filename-dark>{ filename_dark }</filename-dark>{{ endif }}
     <options>{ options }</options>
     <shade_type>{ shade_type }</shade_type>
     <pcolor>{ pcolor }</pcolor>
@@ -24,85 +28,208 @@ static GNOME_WP_LIST_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
     </wallpaper>
 </wallpapers>"#;
 
+// Day from 7:00 to 18:00 and night from 19:00 to 6:00, fading in between
+static GNOME_TIMED_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<background>
+    <starttime>
+        <year>2011</year>
+        <month>11</month>
+        <day>24</day>
+        <hour>7</hour>
+        <minute>00</minute>
+        <second>00</second>
+    </starttime>
+    <static>
+        <duration>39600.0</duration>
+        <file>{ day }</file>
+    </static>
+    <transition type="overlay">
+        <duration>3600.0</duration>
+        <from>{ day }</from>
+        <to>{ night }</to>
+    </transition>
+    <static>
+        <duration>39600.0</duration>
+        <file>{ night }</file>
+    </static>
+    <transition type="overlay">
+        <duration>3600.0</duration>
+        <from>{ night }</from>
+        <to>{ day }</to>
+    </transition>
+</background>"#;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GNOMETimedBackground {
+    day: PathBuf,
+    night: PathBuf,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Name<'a> {
-    locale: &'a str,
+    locale: String,
     name: &'a str,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct GNOMEWallpaperMeta<'a> {
+    /// Credited in comments, the schema has no element for them
+    authors: Vec<String>,
     default_name: Option<&'a String>,
     names: Vec<Name<'a>>,
-    filename: &'a Path,
-    options: &'a PictureOptions,
-    shade_type: &'a ColorShadingType,
-    pcolor: &'a HexColor,
-    scolor: &'a HexColor,
+    filename: PathBuf,
+    filename_dark: Option<PathBuf>,
+    options: &'a str,
+    shade_type: &'a str,
+    // GNOME only takes opaque colors
+    #[serde(serialize_with = "hex_color::rgb::serialize")]
+    pcolor: HexColor,
+    #[serde(serialize_with = "hex_color::rgb::serialize")]
+    scolor: HexColor,
 }
 
 impl<'a> Name<'a> {
-    pub fn flatten<F>(src: &'a Localized<String>, transform: F) -> Result<Vec<Self>>
-    where
-        F: Fn(&Locale) -> &str,
-    {
+    pub fn flatten(src: &'a Localized<String>, format: LocaleFormat) -> Result<Vec<Self>> {
+        // Sorted by locale, so the output is stable
         Ok(src
-            .generate_hashmap(transform)?
+            .sorted_entries()
             .into_iter()
-            .map(|(locale, name)| Self { locale, name })
+            .map(|(locale, name)| Self {
+                locale: locale.format(format),
+                name,
+            })
             .collect())
     }
 }
 
 impl<'a> GNOMEWallpaperMeta<'a> {
-    pub fn new(wallpaper: &'a Wallpaper, base: &Path) -> Result<Self> {
+    pub fn new(
+        wallpaper: &'a Wallpaper,
+        authors: &[&Author],
+        base: &Path,
+        prefix: &Path,
+    ) -> Result<Self> {
+        let authors = authors
+            .iter()
+            .map(|a| {
+                let name = a.name().best_default().map_or("", String::as_str);
+                let mut credit = format!("{} <{}>", name, a.email());
+                // Comments may not contain a double hyphen
+                while credit.contains("--") {
+                    credit = credit.replace("--", "-");
+                }
+                credit
+            })
+            .collect();
         let titles = wallpaper.titles();
-        let default_name = titles.get_default();
-        let names = Name::flatten(titles, |l| l.to_locale())?;
+        // Some GNOME versions want an untagged name, even without a default title
+        let default_name = titles.best_default();
+        let names = Name::flatten(titles, LocaleFormat::Hyphen)?;
         let (pcolor, scolor) = wallpaper.colors();
+        if pcolor.a != u8::MAX || scolor.a != u8::MAX {
+            warn!(
+                "{}: GNOME does not support transparent colors, dropping the alpha",
+                wallpaper.id()
+            );
+        }
+        // GNOME switches between the variants of timed wallpapers itself
+        let (filename, filename_dark) = match wallpaper.timed() {
+            true => (prefix.join(timed_target(wallpaper)), None),
+            false => (
+                wallpaper.target(base, prefix)?,
+                wallpaper.dark_target(base, prefix)?,
+            ),
+        };
         Ok(Self {
+            authors,
             default_name,
             names,
-            filename: wallpaper.target(base),
-            options: wallpaper.option(),
-            shade_type: wallpaper.shade_type(),
+            filename,
+            filename_dark,
+            options: wallpaper.option().to_gnome_str(),
+            shade_type: wallpaper.shade_type().to_gnome_str(),
             pcolor,
             scolor,
         })
     }
 }
 
-pub fn render_gnome<'a>(metadata: &'a Metadata, base: &Path) -> Result<HashMap<&'a str, String>> {
+/// Adds a comment naming the tool that generated `xml` after its XML
+/// declaration.
+pub fn with_stamp(xml: &str, stamp: &str) -> String {
+    let comment = format!("<!-- Generated by {} -->", stamp.replace("--", "-"));
+    match xml.split_once('\n') {
+        Some((declaration, rest)) => format!("{}\n{}\n{}", declaration, comment, rest),
+        None => format!("{}\n{}", comment, xml),
+    }
+}
+
+pub fn render_gnome<'a>(
+    metadata: &'a Metadata,
+    base: &Path,
+    prefix: &Path,
+) -> Result<HashMap<&'a str, String>> {
     let mut template = TinyTemplate::new();
     template.add_template("gnome-wp-list", GNOME_WP_LIST_TEMPLATE)?;
-    let wallpapers = metadata
-        .wallpapers()
-        .ok_or_else(|| eyre!("Failed to get wallpaper list"))?;
+    let wallpapers = metadata.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
     let mut ret = HashMap::new();
     for wallpaper in wallpapers {
-        let target = GNOMEWallpaperMeta::new(wallpaper, base)?;
+        let authors = metadata.authors_of(wallpaper)?;
+        let target = GNOMEWallpaperMeta::new(wallpaper, &authors, base, prefix)?;
         ret.insert(wallpaper.id(), template.render("gnome-wp-list", &target)?);
     }
     Ok(ret)
 }
 
+/// Installed path of the timed background of `wallpaper`, relative to the
+/// install prefix.
+pub fn timed_target(wallpaper: &Wallpaper) -> PathBuf {
+    wallpaper.package_dir().join("gnome-timed.xml")
+}
+
+/// Renders the timed backgrounds of the wallpapers asking for one, which
+/// also need a dark variant.
+pub fn render_gnome_timed<'a>(
+    metadata: &'a Metadata,
+    base: &Path,
+    prefix: &Path,
+) -> Result<HashMap<&'a str, String>> {
+    let mut template = TinyTemplate::new();
+    template.add_template("gnome-timed", GNOME_TIMED_TEMPLATE)?;
+    let wallpapers = metadata.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
+    let mut ret = HashMap::new();
+    for wallpaper in wallpapers.iter().filter(|w| w.timed()) {
+        let Some(night) = wallpaper.dark_target(base, prefix)? else {
+            bail!("{}: timed wallpapers need a dark variant", wallpaper.id());
+        };
+        let background = GNOMETimedBackground {
+            day: wallpaper.target(base, prefix)?,
+            night,
+        };
+        ret.insert(wallpaper.id(), template.render("gnome-timed", &background)?);
+    }
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod test {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
-    use super::render_gnome;
+    use super::{render_gnome, render_gnome_timed, with_stamp};
     use crate::meta::Metadata;
 
     #[test]
     fn test_render() {
         let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
-        let result = render_gnome(&dummy_meta, &PathBuf::from(".")).unwrap();
+        let result =
+            render_gnome(&dummy_meta, &PathBuf::from("."), Path::new("/usr/share")).unwrap();
         assert_eq!(
             result.get("Kusa").unwrap(),
             r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE wallpapers SYSTEM "gnome-wp-list.dtd">
 <wallpapers>
     <wallpaper deleted="false">
+    <!-- Author: Yajuu Senpai <yajuu.senpai@example.com> -->
     <name>Kusa</name>
     <name xml:lang="en-US">Grass</name>
     <filename>/usr/share/wallpapers/Kusa/contents/images/7680x4320.jpg</filename>
@@ -114,4 +241,127 @@ mod test {
 </wallpapers>"#
         );
     }
+
+    #[test]
+    fn test_render_authors() {
+        let meta = crate::meta::test::DUMMY_META.replace("Yajuu Senpai", "Yajuu---Senpai");
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let xml = &render_gnome(&meta, Path::new("."), Path::new("/usr/share")).unwrap()["Kusa"];
+        assert!(xml.contains("<!-- Author: Yajuu-Senpai <yajuu.senpai@example.com> -->\n"));
+
+        let meta = crate::meta::test::DUMMY_META.replace("[[authors]]", "[[other]]");
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let xml = &render_gnome(&meta, Path::new("."), Path::new("/usr/share")).unwrap()["Kusa"];
+        assert!(!xml.contains("<!--"));
+    }
+
+    #[test]
+    fn test_with_stamp() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let xml = &render_gnome(&dummy_meta, &PathBuf::from("."), Path::new("/usr/share")).unwrap()
+            ["Kusa"];
+        assert!(!xml.contains("Generated by"));
+        let stamp = crate::stamp::stamp_at(0);
+        let stamped = with_stamp(xml, &stamp);
+        assert!(stamped.starts_with(&format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- Generated by {} -->\n<!DOCTYPE",
+            stamp
+        )));
+        assert_eq!(
+            stamped.replace(&format!("<!-- Generated by {} -->\n", stamp), ""),
+            *xml
+        );
+    }
+
+    #[test]
+    fn test_render_alpha() {
+        let meta = format!(
+            "{}primary_color = \"#023C8880\"\n",
+            crate::meta::test::DUMMY_META
+        );
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let result = render_gnome(&meta, &PathBuf::from("."), Path::new("/usr/share")).unwrap();
+        assert!(result["Kusa"].contains("<pcolor>#023C88</pcolor>"));
+    }
+
+    #[test]
+    fn test_render_without_default() {
+        let meta = toml::from_str::<Metadata>(
+            &crate::meta::test::DUMMY_META
+                .replace(r#"title.default = "Kusa""#, r#"title.zh-CN = "草""#),
+        )
+        .unwrap();
+        let result = render_gnome(&meta, &PathBuf::from("."), Path::new("/usr/share")).unwrap();
+        let xml = result.get("Kusa").unwrap();
+        assert!(xml.contains("\n    <name>Grass</name>\n"));
+        assert!(xml.contains(r#"<name xml:lang="en-US">Grass</name>"#));
+        assert!(xml.contains(r#"<name xml:lang="zh-CN">草</name>"#));
+    }
+
+    #[test]
+    fn test_render_dark_only() {
+        let meta = crate::test::DUMMY_TREE_META
+            .replace(r#"path = "kusa.png""#, r#"path = ["kusa-dark.png"]"#);
+        let src = crate::test::dummy_tree(&meta);
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let xml = &render_gnome(&meta, src.path(), Path::new("/usr/share")).unwrap()["Kusa"];
+        assert!(xml
+            .contains("<filename>/usr/share/wallpapers/Kusa/contents/images/64x36.png</filename>"));
+        assert!(!xml.contains("filename-dark"));
+    }
+
+    #[test]
+    fn test_render_timed() {
+        let meta = crate::test::DUMMY_TREE_META.replace(
+            r#"path = "kusa.png""#,
+            "path = [\"kusa.png\", \"kusa-dark.png\"]\ntimed = true",
+        );
+        let src = crate::test::dummy_tree(&meta);
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let prefix = Path::new("/usr/share");
+        let timed = render_gnome_timed(&meta, src.path(), prefix).unwrap();
+        assert_eq!(
+            timed["Kusa"],
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<background>
+    <starttime>
+        <year>2011</year>
+        <month>11</month>
+        <day>24</day>
+        <hour>7</hour>
+        <minute>00</minute>
+        <second>00</second>
+    </starttime>
+    <static>
+        <duration>39600.0</duration>
+        <file>/usr/share/wallpapers/Kusa/contents/images/64x36.png</file>
+    </static>
+    <transition type="overlay">
+        <duration>3600.0</duration>
+        <from>/usr/share/wallpapers/Kusa/contents/images/64x36.png</from>
+        <to>/usr/share/wallpapers/Kusa/contents/images_dark/64x36.png</to>
+    </transition>
+    <static>
+        <duration>39600.0</duration>
+        <file>/usr/share/wallpapers/Kusa/contents/images_dark/64x36.png</file>
+    </static>
+    <transition type="overlay">
+        <duration>3600.0</duration>
+        <from>/usr/share/wallpapers/Kusa/contents/images_dark/64x36.png</from>
+        <to>/usr/share/wallpapers/Kusa/contents/images/64x36.png</to>
+    </transition>
+</background>"#
+        );
+        // The wallpaper list points at the timed background instead
+        let xml = &render_gnome(&meta, src.path(), prefix).unwrap()["Kusa"];
+        assert!(xml.contains("<filename>/usr/share/wallpapers/Kusa/gnome-timed.xml</filename>"));
+        assert!(!xml.contains("filename-dark"));
+
+        let meta = toml::from_str::<Metadata>(
+            &crate::test::DUMMY_TREE_META
+                .replace(r#"path = "kusa.png""#, "path = \"kusa.png\"\ntimed = true"),
+        )
+        .unwrap();
+        assert!(render_gnome_timed(&meta, src.path(), prefix).is_err());
+    }
 }
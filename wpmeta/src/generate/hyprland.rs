@@ -0,0 +1,67 @@
+use eyre::Result;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::WpmetaError;
+use crate::meta::Metadata;
+
+pub fn render_hyprland<'a>(
+    metadata: &'a Metadata,
+    base: &Path,
+    prefix: &Path,
+) -> Result<HashMap<&'a str, String>> {
+    let wallpapers = metadata.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
+    let mut ret = HashMap::new();
+    for wallpaper in wallpapers {
+        let path = wallpaper.target(base, prefix)?;
+        let mut config = format!(
+            "preload = {}\nwallpaper = ,{}\n",
+            path.display(),
+            path.display()
+        );
+        // hyprpaper has no notion of variants, so the dark one is only
+        // pointed out
+        if let Some(dark) = wallpaper.dark_target(base, prefix)? {
+            config.push_str(&format!("# dark: {}\n", dark.display()));
+        }
+        ret.insert(wallpaper.id(), config);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::render_hyprland;
+    use crate::meta::Metadata;
+
+    #[test]
+    fn test_render() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result =
+            render_hyprland(&dummy_meta, &PathBuf::from("."), Path::new("/usr/share")).unwrap();
+        assert_eq!(
+            result.get("Kusa").unwrap(),
+            r#"preload = /usr/share/wallpapers/Kusa/contents/images/7680x4320.jpg
+wallpaper = ,/usr/share/wallpapers/Kusa/contents/images/7680x4320.jpg
+"#
+        );
+
+        let meta = crate::test::DUMMY_TREE_META.replace(
+            r#"path = "kusa.png""#,
+            r#"path = ["kusa.png", "kusa-dark.png"]"#,
+        );
+        let src = crate::test::dummy_tree(&meta);
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let result = render_hyprland(&meta, src.path(), Path::new("/usr/share")).unwrap();
+        assert_eq!(
+            result.get("Kusa").unwrap(),
+            r#"preload = /usr/share/wallpapers/Kusa/contents/images/64x36.png
+wallpaper = ,/usr/share/wallpapers/Kusa/contents/images/64x36.png
+# dark: /usr/share/wallpapers/Kusa/contents/images_dark/64x36.png
+"#
+        );
+    }
+}
@@ -1,15 +1,21 @@
-use eyre::{eyre, Result};
+use eyre::Result;
 use serde::ser::{SerializeMap, Serializer};
 use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
 
 use std::collections::HashMap;
 
-use locale::Localized;
+use locale::{LocaleFormat, Localized};
 
-use crate::meta::{Author, Metadata};
+use crate::error::WpmetaError;
+use crate::meta::{Author, Category, Metadata};
+
+/// Package structure of Plasma image wallpapers
+pub const KPACKAGE_STRUCTURE: &str = "Wallpaper/Images";
 
 #[derive(Clone, Debug)]
 pub struct KPluginName<'a> {
+    key: &'static str,
     inner: &'a Localized<String>,
 }
 
@@ -19,22 +25,49 @@ pub struct KPluginAuthor<'a> {
     email: &'a str,
     #[serde(flatten)]
     name: KPluginName<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'a str>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct KPluginMetadataInner<'a> {
     authors: Vec<KPluginAuthor<'a>>,
+    #[serde(flatten)]
+    description: Option<KPluginName<'a>>,
     id: &'a str,
     license: &'a str,
     #[serde(flatten)]
     name: KPluginName<'a>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<&'static str>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct KPluginMetadata<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    k_package_structure: Option<&'static str>,
     k_plugin: KPluginMetadataInner<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blur_hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generated_by: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KDECollection<'a> {
+    id: &'a str,
+    #[serde(flatten)]
+    name: KPluginName<'a>,
+    #[serde(flatten)]
+    description: Option<KPluginName<'a>>,
+    wallpapers: Vec<&'a str>,
 }
 
 impl<'a> Serialize for KPluginName<'a> {
@@ -42,23 +75,30 @@ impl<'a> Serialize for KPluginName<'a> {
     where
         S: Serializer,
     {
-        let mut map = serializer.serialize_map(Some(self.inner.len()))?;
-        if let Some(default) = self.inner.get_default() {
-            map.serialize_entry("Name", default)?;
+        // The promoted default may add an entry, so no length hint
+        let mut map = serializer.serialize_map(None)?;
+        // KDE requires the untranslated key
+        if let Some(default) = self.inner.best_default() {
+            map.serialize_entry(self.key, default)?;
         }
-        let flattened = self.inner.generate_hashmap(|l| l.to_locale());
-        if let Ok(names) = flattened {
-            for (locale, name) in names {
-                map.serialize_entry(&format!("Name[{}]", locale.replace('-', "_")), name)?;
-            }
+        // Translations come sorted by locale, so the output is stable
+        for (locale, name) in self.inner.sorted_entries() {
+            let key = format!("{}[{}]", self.key, locale.format(LocaleFormat::Underscore));
+            map.serialize_entry(&key, name)?;
         }
         map.end()
     }
 }
 
+impl<'a> KPluginName<'a> {
+    pub fn with_key(key: &'static str, inner: &'a Localized<String>) -> Self {
+        Self { key, inner }
+    }
+}
+
 impl<'a> From<&'a Localized<String>> for KPluginName<'a> {
     fn from(value: &'a Localized<String>) -> Self {
-        Self { inner: value }
+        Self::with_key("Name", value)
     }
 }
 
@@ -67,65 +107,135 @@ impl<'a> From<&'a Author> for KPluginAuthor<'a> {
         Self {
             email: value.email(),
             name: value.name().into(),
+            role: value.role(),
         }
     }
 }
 
 impl<'a> KPluginMetadataInner<'a> {
-    pub fn new(authors: Vec<KPluginAuthor<'a>>, id: &'a str, license: &'a str, name: KPluginName<'a>) -> Self {
+    pub fn new(
+        authors: Vec<KPluginAuthor<'a>>,
+        description: Option<KPluginName<'a>>,
+        id: &'a str,
+        license: &'a str,
+        name: KPluginName<'a>,
+        tags: &'a [String],
+        category: Option<Category>,
+    ) -> Self {
         Self {
             authors,
+            description,
             id,
             license,
             name,
+            tags,
+            category: category.map(Category::to_kde_str),
         }
     }
 }
 
 impl<'a> KPluginMetadata<'a> {
-    pub fn from_metadata(src: &'a Metadata) -> Result<HashMap<&'a str, Self>> {
-        let authors = match src.authors() {
-            Some(authors) => authors.iter().map(KPluginAuthor::from).collect(),
-            None => Vec::new(),
-        };
-        let wallpapers = src
-            .wallpapers()
-            .ok_or_else(|| eyre!("Failed to get wallpaper list"))?;
-        Ok(wallpapers
+    /// Builds the metadata of every wallpaper, declaring the Plasma package
+    /// structure if `package_structure` is set and the tool that generated
+    /// it if given a `stamp`.
+    pub fn from_metadata(
+        src: &'a Metadata,
+        package_structure: bool,
+        stamp: Option<&str>,
+    ) -> Result<HashMap<&'a str, Self>> {
+        let wallpapers = src.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
+        wallpapers
             .iter()
             .map(|w| {
-                (
+                let authors = src
+                    .authors_of(w)?
+                    .into_iter()
+                    .map(KPluginAuthor::from)
+                    .collect();
+                Ok((
                     w.id(),
                     Self {
-                        k_plugin: KPluginMetadataInner::new(authors.clone(), w.id(), w.license(), w.titles().into())
+                        k_package_structure: package_structure.then_some(KPACKAGE_STRUCTURE),
+                        k_plugin: KPluginMetadataInner::new(
+                            authors,
+                            w.description()
+                                .map(|d| KPluginName::with_key("Description", d)),
+                            w.id(),
+                            w.license(),
+                            w.titles().into(),
+                            w.tags(),
+                            w.category(),
+                        ),
+                        blur_hash: w.blurhash(),
+                        aspect_ratio: w.aspect_ratio().map(|r| r.to_string()),
+                        generated_by: stamp.map(str::to_owned),
                     },
-                )
+                ))
             })
-            .collect())
+            .collect()
     }
 }
 
-pub fn render_kde(metadata: &Metadata) -> Result<HashMap<&str, String>> {
-    Ok(KPluginMetadata::from_metadata(metadata)?
-        .into_iter()
-        .map(|(k, v)| {
-            (
-                k,
-                serde_json::to_string_pretty(&v).expect("Unable to serialize KPlugin Metadata"),
-            )
-        })
-        .collect())
+/// Serializes `value` indented by `indent` spaces, or on a single line if
+/// `indent` is 0.
+fn to_json<T: Serialize>(value: &T, indent: usize) -> serde_json::Result<String> {
+    if indent == 0 {
+        return serde_json::to_string(value);
+    }
+    let indent = " ".repeat(indent);
+    let mut buf = Vec::new();
+    let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    value.serialize(&mut serializer)?;
+    // serde_json only emits valid UTF-8
+    Ok(String::from_utf8(buf).expect("Invalid UTF-8 in serialized JSON"))
+}
+
+/// Renders the index of the collection the wallpapers belong to, if any.
+pub fn render_kde_collection(metadata: &Metadata, indent: usize) -> Result<Option<String>> {
+    let Some(collection) = metadata.collection() else {
+        return Ok(None);
+    };
+    let wallpapers = metadata.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
+    let index = KDECollection {
+        id: collection.id(),
+        name: collection.name().into(),
+        description: collection
+            .description()
+            .map(|d| KPluginName::with_key("Description", d)),
+        wallpapers: wallpapers.iter().map(|w| w.id()).collect(),
+    };
+    Ok(Some(to_json(&index, indent)?))
+}
+
+pub fn render_kde<'a>(
+    metadata: &'a Metadata,
+    indent: usize,
+    package_structure: bool,
+    stamp: Option<&str>,
+) -> Result<HashMap<&'a str, String>> {
+    Ok(
+        KPluginMetadata::from_metadata(metadata, package_structure, stamp)?
+            .into_iter()
+            .map(|(k, v)| {
+                (
+                    k,
+                    to_json(&v, indent).expect("Unable to serialize KPlugin Metadata"),
+                )
+            })
+            .collect(),
+    )
 }
 
 #[cfg(test)]
 mod test {
-    use super::render_kde;
-    use crate::meta::Metadata;
+    use super::{render_kde, render_kde_collection};
+    use crate::meta::{Category, Metadata};
 
     #[test]
     fn test_render() {
         let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
-        let result = render_kde(&dummy_meta).unwrap();
+        let result = render_kde(&dummy_meta, 2, false, None).unwrap();
         assert_eq!(
             result.get("Kusa").unwrap(),
             r#"{
@@ -145,4 +255,234 @@ mod test {
 }"#
         );
     }
+
+    #[test]
+    fn test_render_compact() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert_eq!(
+            result.get("Kusa").unwrap(),
+            r#"{"KPlugin":{"Authors":[{"Email":"yajuu.senpai@example.com","Name":"Yajuu Senpai","Name[zh_CN]":"野兽先辈"}],"Id":"Kusa","License":"CC BY-SA 4.0","Name":"Kusa","Name[en_US]":"Grass"}}"#
+        );
+    }
+
+    #[test]
+    fn test_render_tags() {
+        let meta = format!(
+            "{}tags = [\"nature\", \"grass\"]\n",
+            crate::meta::test::DUMMY_META
+        );
+        let dummy_meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert!(result
+            .get("Kusa")
+            .unwrap()
+            .ends_with(r#""Name":"Kusa","Name[en_US]":"Grass","Tags":["nature","grass"]}}"#));
+    }
+
+    #[test]
+    fn test_render_collection() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        assert!(render_kde_collection(&dummy_meta, 2).unwrap().is_none());
+        let meta = toml::from_str::<Metadata>(
+            r#"
+            [collection]
+            id = "Plants"
+            name.default = "Plants"
+            name.zh-CN = "植物"
+            description.default = "Green things"
+
+            [[wallpapers]]
+            title.default = "Kusa"
+            license = "CC-BY-SA-4.0"
+            id = "Kusa"
+            path = "kusa.jpg"
+
+            [[wallpapers]]
+            title.default = "Hana"
+            license = "CC-BY-SA-4.0"
+            id = "Hana"
+            path = "hana.jpg"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            render_kde_collection(&meta, 2).unwrap().unwrap(),
+            r#"{
+  "Id": "Plants",
+  "Name": "Plants",
+  "Name[zh_CN]": "植物",
+  "Description": "Green things",
+  "Wallpapers": [
+    "Kusa",
+    "Hana"
+  ]
+}"#
+        );
+    }
+
+    #[test]
+    fn test_render_author_roles() {
+        let meta = toml::from_str::<Metadata>(
+            r#"
+            [[authors]]
+            email = "photo@example.com"
+            name.default = "Photographer"
+            role = "Photography"
+
+            [[authors]]
+            email = "edit@example.com"
+            name.default = "Retoucher"
+
+            [[wallpapers]]
+            title.default = "Kusa"
+            license = "CC0-1.0"
+            id = "Kusa"
+            path = "kusa.jpg"
+            authors = ["edit@example.com", "photo@example.com"]
+
+            [[wallpapers]]
+            title.default = "Hana"
+            license = "CC0-1.0"
+            id = "Hana"
+            path = "hana.jpg"
+            "#,
+        )
+        .unwrap();
+        let result = render_kde(&meta, 0, false, None).unwrap();
+        assert!(result.get("Kusa").unwrap().starts_with(
+            r#"{"KPlugin":{"Authors":[{"Email":"edit@example.com","Name":"Retoucher"},{"Email":"photo@example.com","Name":"Photographer","Role":"Photography"}]"#
+        ));
+        assert!(result.get("Hana").unwrap().starts_with(
+            r#"{"KPlugin":{"Authors":[{"Email":"photo@example.com","Name":"Photographer","Role":"Photography"},{"Email":"edit@example.com","Name":"Retoucher"}]"#
+        ));
+
+        let meta = toml::from_str::<Metadata>(&format!(
+            "{}authors = [\"nobody@example.com\"]\n",
+            crate::meta::test::DUMMY_META
+        ))
+        .unwrap();
+        assert!(render_kde(&meta, 0, false, None).is_err());
+    }
+
+    #[test]
+    fn test_render_without_default() {
+        let meta = toml::from_str::<Metadata>(
+            r#"
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.zh-CN = "野兽先辈"
+            name.ja-JP = "野獣先輩"
+
+            [[wallpapers]]
+            title.zh-CN = "草"
+            title.en-US = "Grass"
+            license = "CC0-1.0"
+            id = "Kusa"
+            path = "kusa.jpg"
+            "#,
+        )
+        .unwrap();
+        let result: serde_json::Value = serde_json::from_str(
+            render_kde(&meta, 0, false, None)
+                .unwrap()
+                .get("Kusa")
+                .unwrap(),
+        )
+        .unwrap();
+        let plugin = &result["KPlugin"];
+        assert_eq!(plugin["Name"], "Grass");
+        assert_eq!(plugin["Name[zh_CN]"], "草");
+        assert_eq!(plugin["Authors"][0]["Name"], "野獣先輩");
+    }
+
+    #[test]
+    fn test_render_description() {
+        let meta = format!(
+            "{}description.default = \"A patch of grass\"\ndescription.zh-CN = \"一片草地\"\n",
+            crate::meta::test::DUMMY_META
+        );
+        let dummy_meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&result["Kusa"]).unwrap();
+        assert_eq!(json["KPlugin"]["Description"], "A patch of grass");
+        assert_eq!(json["KPlugin"]["Description[zh_CN]"], "一片草地");
+        // Without a description there is no field at all
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert!(!result["Kusa"].contains("Description"));
+    }
+
+    #[test]
+    fn test_render_package_structure() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result = render_kde(&dummy_meta, 0, true, None).unwrap();
+        assert!(result["Kusa"]
+            .starts_with(r#"{"KPackageStructure":"Wallpaper/Images","KPlugin":{"Authors":"#));
+        let json: serde_json::Value = serde_json::from_str(&result["Kusa"]).unwrap();
+        assert_eq!(json["KPlugin"]["Id"], "Kusa");
+    }
+
+    #[test]
+    fn test_render_locale_order() {
+        let meta = crate::meta::test::DUMMY_META.replace(
+            "title.en-US = \"Grass\"",
+            "title.zh-TW = \"草\"\ntitle.ja-JP = \"草原\"\ntitle.zh-CN = \"草\"\ntitle.en-US = \"Grass\"",
+        );
+        let dummy_meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert!(result["Kusa"].ends_with(
+            r#""Name":"Kusa","Name[en_US]":"Grass","Name[ja_JP]":"草原","Name[zh_CN]":"草","Name[zh_TW]":"草"}}"#
+        ));
+    }
+
+    #[test]
+    fn test_render_stamp() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert!(!result["Kusa"].contains("GeneratedBy"));
+        let stamp = crate::stamp::stamp_at(0);
+        let result = render_kde(&dummy_meta, 0, false, Some(&stamp)).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&result["Kusa"]).unwrap();
+        assert_eq!(json["GeneratedBy"], stamp.as_str());
+    }
+
+    #[test]
+    fn test_render_aspect_ratio() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert!(!result["Kusa"].contains("AspectRatio"));
+        let src = crate::test::dummy_tree(crate::test::DUMMY_TREE_META);
+        let mut meta = toml::from_str::<Metadata>(crate::test::DUMMY_TREE_META).unwrap();
+        for wallpaper in meta.wallpapers_mut() {
+            wallpaper.tag_aspect_ratio(src.path()).unwrap();
+        }
+        let result = render_kde(&meta, 0, false, None).unwrap();
+        assert!(result["Kusa"].ends_with(r#","AspectRatio":"16:9"}"#));
+    }
+
+    #[test]
+    fn test_render_category() {
+        let with = |category: &str| {
+            let meta = format!(
+                "{}category = {:?}\n",
+                crate::meta::test::DUMMY_META,
+                category
+            );
+            toml::from_str::<Metadata>(&meta)
+        };
+        let meta = with("nature").unwrap();
+        assert_eq!(
+            meta.wallpapers().unwrap()[0].category(),
+            Some(Category::Nature)
+        );
+        let result = render_kde(&meta, 0, false, None).unwrap();
+        assert!(result["Kusa"].contains(r#""Category":"Nature""#));
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result = render_kde(&dummy_meta, 0, false, None).unwrap();
+        assert!(!result["Kusa"].contains("Category"));
+
+        let err = with("grass").unwrap_err().to_string();
+        assert!(err.contains("unknown variant `grass`"), "{}", err);
+    }
 }
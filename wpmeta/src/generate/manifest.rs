@@ -0,0 +1,108 @@
+use eyre::Result;
+
+use std::path::PathBuf;
+
+use super::{Generator, Manifests, RenderContext};
+use crate::error::WpmetaError;
+use crate::meta::Metadata;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestContent {
+    Text(String),
+    /// A symlink to another installed manifest
+    Link(PathBuf),
+}
+
+/// A rendered manifest, its path is relative to the destination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub path: PathBuf,
+    pub content: ManifestContent,
+}
+
+impl Manifest {
+    pub(super) fn text(path: PathBuf, content: &str) -> Self {
+        Self {
+            path,
+            content: ManifestContent::Text(content.to_owned()),
+        }
+    }
+}
+
+/// Renders the manifests of every wallpaper in `metadata` with each of
+/// `generators` without touching the disk, keyed by wallpaper id.
+pub fn render_manifests<'a>(
+    metadata: &'a Metadata,
+    generators: &[Box<dyn Generator>],
+    ctx: &RenderContext,
+) -> Result<Manifests<'a>> {
+    let wallpapers = metadata.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
+    let mut ret: Manifests = wallpapers.iter().map(|w| (w.id(), Vec::new())).collect();
+    for generator in generators {
+        for (id, manifests) in generator.render(metadata, ctx)? {
+            ret.entry(id).or_default().extend(manifests);
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{render_manifests, Manifest, ManifestContent};
+    use crate::generate::{
+        builtin_generators, generators_for, render_gnome, RenderContext, Target,
+    };
+    use crate::meta::Metadata;
+
+    #[test]
+    fn test_render_manifests() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let base = PathBuf::from(".");
+        let installed = Path::new("/usr/share");
+        let gnome = render_gnome(&dummy_meta, &base, installed).unwrap()["Kusa"].clone();
+        let all_targets = Target::all();
+        let ctx = |targets| RenderContext {
+            base: &base,
+            prefix: Path::new("usr/share"),
+            installed,
+            targets,
+            indent: 2,
+            kde_package_structure: false,
+            stamp: None,
+        };
+
+        let all = render_manifests(&dummy_meta, &builtin_generators(), &ctx(&all_targets)).unwrap();
+        let paths = all["Kusa"].iter().map(|m| &m.path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "usr/share/gnome-background-properties/Kusa.xml",
+                "usr/share/cinnamon-background-properties/Kusa.xml",
+                "usr/share/budgie-background-properties/Kusa.xml",
+                "usr/share/mate-background-properties/Kusa.xml",
+                "usr/share/wallpapers/Kusa/metadata.json",
+                "usr/share/wpmeta/hyprland/Kusa.conf",
+                "usr/share/wpmeta/sway/Kusa.conf",
+            ]
+        );
+        assert_eq!(all["Kusa"][0].content, ManifestContent::Text(gnome.clone()));
+        assert_eq!(
+            all["Kusa"][3].content,
+            ManifestContent::Link("/usr/share/gnome-background-properties/Kusa.xml".into())
+        );
+
+        // MATE gets its own copy without GNOME
+        let targets = [Target::Mate];
+        let mate =
+            render_manifests(&dummy_meta, &generators_for(&targets), &ctx(&targets)).unwrap();
+        assert_eq!(
+            mate["Kusa"],
+            [Manifest {
+                path: "usr/share/mate-background-properties/Kusa.xml".into(),
+                content: ManifestContent::Text(gnome),
+            }]
+        );
+    }
+}
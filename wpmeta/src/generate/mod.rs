@@ -1,5 +1,122 @@
+use clap::ValueEnum;
+use eyre::Result;
+use hex_color::HexColor;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::WpmetaError;
+use crate::meta::Wallpaper;
+
+mod catalog;
 mod gnome;
+mod hyprland;
 mod kde;
+mod manifest;
+mod registry;
+mod sway;
+
+pub use catalog::{CatalogEntry, WallpaperCatalog};
+pub use gnome::{render_gnome, render_gnome_timed, timed_target, with_stamp};
+pub use hyprland::render_hyprland;
+pub use kde::{render_kde, render_kde_collection};
+pub use manifest::{render_manifests, Manifest, ManifestContent};
+pub use registry::{
+    builtin_generators, generators_for, Generator, GnomeGenerator, HyprlandGenerator, KdeGenerator,
+    Manifests, RenderContext, SwayGenerator,
+};
+pub use sway::render_sway;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Target {
+    Kde,
+    Gnome,
+    Mate,
+    Cinnamon,
+    Budgie,
+    Hyprland,
+    Sway,
+}
+
+impl Target {
+    pub fn all() -> Vec<Self> {
+        Self::value_variants().to_vec()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ImageReport {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Everything generated for a single wallpaper, paths are relative to the
+/// destination directory.
+#[derive(Clone, Debug, Serialize)]
+pub struct WallpaperReport {
+    pub manifests: Vec<PathBuf>,
+    pub images: Vec<ImageReport>,
+    pub preview: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_previews: Vec<PathBuf>,
+    pub license: String,
+    pub primary_color: HexColor,
+    pub secondary_color: HexColor,
+}
+
+/// Report of a whole run, shared between the parallel generation tasks.
+#[derive(Debug, Default)]
+pub struct GenerationReport {
+    wallpapers: Mutex<BTreeMap<String, WallpaperReport>>,
+}
+
+impl WallpaperReport {
+    pub fn new(wallpaper: &Wallpaper, base: &Path, prefix: &Path) -> Result<Self, WpmetaError> {
+        let images = wallpaper
+            .sources()
+            .map(|file| file.get_meta(base))
+            .chain(wallpaper.file().get_dark_meta(base).transpose())
+            .map(|meta| {
+                let meta = meta?;
+                let (width, height) = meta.dimensions();
+                Ok(ImageReport {
+                    path: prefix.join(meta.target()),
+                    width,
+                    height,
+                })
+            })
+            .collect::<Result<_, WpmetaError>>()?;
+        let (primary_color, secondary_color) = wallpaper.colors();
+        Ok(Self {
+            manifests: Vec::new(),
+            images,
+            preview: None,
+            extra_previews: Vec::new(),
+            license: wallpaper
+                .canonical_license()
+                .unwrap_or_else(|_| wallpaper.license().to_owned()),
+            primary_color,
+            secondary_color,
+        })
+    }
+}
+
+impl GenerationReport {
+    pub fn insert(&self, id: &str, report: WallpaperReport) {
+        self.wallpapers
+            .lock()
+            .expect("Generation report lock poisoned")
+            .insert(id.to_owned(), report);
+    }
 
-pub use gnome::render_gnome;
-pub use kde::render_kde;
+    pub fn to_json(&self) -> Result<String> {
+        let wallpapers = self
+            .wallpapers
+            .lock()
+            .expect("Generation report lock poisoned");
+        Ok(serde_json::to_string_pretty(&*wallpapers)?)
+    }
+}
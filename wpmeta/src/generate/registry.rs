@@ -0,0 +1,243 @@
+use eyre::Result;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{
+    render_gnome, render_gnome_timed, render_hyprland, render_kde, render_sway, timed_target,
+    with_stamp, Manifest, ManifestContent, Target,
+};
+use crate::error::WpmetaError;
+use crate::meta::Metadata;
+use crate::paths::{
+    BUDGIE_META_BASE, CINNAMON_META_BASE, GNOME_META_BASE, HYPRLAND_META_BASE, MATE_META_BASE,
+    SWAY_META_BASE,
+};
+
+/// Manifests of every wallpaper of a metadata file, keyed by wallpaper id.
+pub type Manifests<'a> = HashMap<&'a str, Vec<Manifest>>;
+
+/// Where and how manifests are rendered.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderContext<'a> {
+    /// Directory the metadata file was read from
+    pub base: &'a Path,
+    /// Install prefix manifests are placed under
+    pub prefix: &'a Path,
+    /// Where the prefix ends up on the installed system
+    pub installed: &'a Path,
+    /// Every target of the run, including those of other generators
+    pub targets: &'a [Target],
+    /// Indentation of JSON manifests in spaces
+    pub indent: usize,
+    /// Declare the Plasma package structure in KDE metadata
+    pub kde_package_structure: bool,
+    /// Tool version and time of the run, noted in manifests if given
+    pub stamp: Option<&'a str>,
+}
+
+/// Renders manifests for the desktops of one or more targets.
+pub trait Generator: Send + Sync {
+    /// Targets this generator writes manifests for.
+    fn targets(&self) -> &'static [Target];
+
+    /// Renders the manifests of every wallpaper in `metadata`.
+    fn render<'a>(&self, metadata: &'a Metadata, ctx: &RenderContext) -> Result<Manifests<'a>>;
+}
+
+/// KDE Plasma wallpaper packages.
+pub struct KdeGenerator;
+
+/// GNOME background properties, shared by the desktops using its schema.
+pub struct GnomeGenerator;
+
+pub struct HyprlandGenerator;
+
+pub struct SwayGenerator;
+
+/// Puts each rendered manifest at the path given by `path`.
+fn text_manifests<'a>(
+    rendered: HashMap<&'a str, String>,
+    path: impl Fn(&str) -> PathBuf,
+) -> Manifests<'a> {
+    rendered
+        .into_iter()
+        .map(|(id, content)| (id, vec![Manifest::text(path(id), &content)]))
+        .collect()
+}
+
+impl Generator for KdeGenerator {
+    fn targets(&self) -> &'static [Target] {
+        &[Target::Kde]
+    }
+
+    fn render<'a>(&self, metadata: &'a Metadata, ctx: &RenderContext) -> Result<Manifests<'a>> {
+        let mut metas = render_kde(metadata, ctx.indent, ctx.kde_package_structure, ctx.stamp)?;
+        let mut ret = HashMap::new();
+        for wallpaper in metadata.wallpapers().into_iter().flatten() {
+            let id = wallpaper.id();
+            let path = ctx
+                .prefix
+                .join(wallpaper.package_dir())
+                .join("metadata.json");
+            let content = metas
+                .remove(id)
+                .ok_or_else(|| WpmetaError::MissingManifest {
+                    id: id.to_owned(),
+                    kind: "KDE",
+                })?;
+            ret.insert(id, vec![Manifest::text(path, &content)]);
+        }
+        Ok(ret)
+    }
+}
+
+impl Generator for GnomeGenerator {
+    fn targets(&self) -> &'static [Target] {
+        &[
+            Target::Gnome,
+            Target::Mate,
+            Target::Cinnamon,
+            Target::Budgie,
+        ]
+    }
+
+    fn render<'a>(&self, metadata: &'a Metadata, ctx: &RenderContext) -> Result<Manifests<'a>> {
+        let targets = ctx.targets;
+        let stamped = |rendered: HashMap<&'a str, String>| -> HashMap<&'a str, String> {
+            match ctx.stamp {
+                Some(stamp) => rendered
+                    .into_iter()
+                    .map(|(id, xml)| (id, with_stamp(&xml, stamp)))
+                    .collect(),
+                None => rendered,
+            }
+        };
+        let gnome_metas = stamped(render_gnome(metadata, ctx.base, ctx.installed)?);
+        let timed_metas = stamped(render_gnome_timed(metadata, ctx.base, ctx.installed)?);
+        let mut ret = HashMap::new();
+        for wallpaper in metadata.wallpapers().into_iter().flatten() {
+            let id = wallpaper.id();
+            let gnome_meta = gnome_metas
+                .get(id)
+                .ok_or_else(|| WpmetaError::MissingManifest {
+                    id: id.to_owned(),
+                    kind: "GNOME",
+                })?;
+            let gnome_meta_file = format!("{}.xml", id);
+            let mut manifests = Vec::new();
+            // Desktops sharing the GNOME schema get a copy each
+            for (target, meta_base) in [
+                (Target::Gnome, GNOME_META_BASE),
+                (Target::Cinnamon, CINNAMON_META_BASE),
+                (Target::Budgie, BUDGIE_META_BASE),
+            ] {
+                if targets.contains(&target) {
+                    let path = ctx.prefix.join(meta_base).join(&gnome_meta_file);
+                    manifests.push(Manifest::text(path, gnome_meta));
+                }
+            }
+            if let Some(timed) = timed_metas.get(id) {
+                let path = ctx.prefix.join(timed_target(wallpaper));
+                manifests.push(Manifest::text(path, timed));
+            }
+            if targets.contains(&Target::Mate) {
+                let path = ctx.prefix.join(MATE_META_BASE).join(&gnome_meta_file);
+                if targets.contains(&Target::Gnome) {
+                    // Link to the GNOME manifest instead of duplicating it
+                    manifests.push(Manifest {
+                        path,
+                        content: ManifestContent::Link(
+                            ctx.installed.join(GNOME_META_BASE).join(&gnome_meta_file),
+                        ),
+                    });
+                } else {
+                    // The GNOME manifest won't be there to link to
+                    manifests.push(Manifest::text(path, gnome_meta));
+                }
+            }
+            ret.insert(id, manifests);
+        }
+        Ok(ret)
+    }
+}
+
+impl Generator for HyprlandGenerator {
+    fn targets(&self) -> &'static [Target] {
+        &[Target::Hyprland]
+    }
+
+    fn render<'a>(&self, metadata: &'a Metadata, ctx: &RenderContext) -> Result<Manifests<'a>> {
+        let rendered = render_hyprland(metadata, ctx.base, ctx.installed)?;
+        Ok(text_manifests(rendered, |id| {
+            ctx.prefix
+                .join(HYPRLAND_META_BASE)
+                .join(format!("{}.conf", id))
+        }))
+    }
+}
+
+impl Generator for SwayGenerator {
+    fn targets(&self) -> &'static [Target] {
+        &[Target::Sway]
+    }
+
+    fn render<'a>(&self, metadata: &'a Metadata, ctx: &RenderContext) -> Result<Manifests<'a>> {
+        let rendered = render_sway(metadata, ctx.base, ctx.installed)?;
+        Ok(text_manifests(rendered, |id| {
+            ctx.prefix.join(SWAY_META_BASE).join(format!("{}.conf", id))
+        }))
+    }
+}
+
+/// Every built-in generator, in the order their manifests are listed.
+pub fn builtin_generators() -> Vec<Box<dyn Generator>> {
+    vec![
+        Box::new(GnomeGenerator),
+        Box::new(KdeGenerator),
+        Box::new(HyprlandGenerator),
+        Box::new(SwayGenerator),
+    ]
+}
+
+/// The built-in generators writing manifests for any of `targets`.
+pub fn generators_for(targets: &[Target]) -> Vec<Box<dyn Generator>> {
+    builtin_generators()
+        .into_iter()
+        .filter(|g| g.targets().iter().any(|t| targets.contains(t)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{generators_for, Generator, GnomeGenerator, RenderContext};
+    use crate::generate::{render_manifests, Target};
+    use crate::meta::Metadata;
+
+    #[test]
+    fn test_generators_for() {
+        let generators = generators_for(&[Target::Gnome]);
+        assert_eq!(generators.len(), 1);
+        assert_eq!(generators[0].targets(), GnomeGenerator.targets());
+        // Targets sharing a generator select it once
+        assert_eq!(generators_for(&[Target::Mate, Target::Budgie]).len(), 1);
+        assert_eq!(generators_for(&Target::all()).len(), 4);
+        assert!(generators_for(&[]).is_empty());
+
+        let meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let ctx = RenderContext {
+            base: Path::new("."),
+            prefix: Path::new("usr/share"),
+            installed: Path::new("/usr/share"),
+            targets: &[Target::Gnome],
+            indent: 2,
+            kde_package_structure: false,
+            stamp: None,
+        };
+        let manifests = render_manifests(&meta, &generators, &ctx).unwrap();
+        let paths: Vec<_> = manifests.values().flatten().map(|m| &m.path).collect();
+        assert_eq!(paths, ["usr/share/gnome-background-properties/Kusa.xml"]);
+    }
+}
@@ -0,0 +1,54 @@
+use eyre::Result;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::WpmetaError;
+use crate::meta::{Metadata, PictureOptions};
+
+pub fn render_sway<'a>(
+    metadata: &'a Metadata,
+    base: &Path,
+    prefix: &Path,
+) -> Result<HashMap<&'a str, String>> {
+    let wallpapers = metadata.wallpapers().ok_or(WpmetaError::NoWallpapers)?;
+    let mut ret = HashMap::new();
+    for wallpaper in wallpapers {
+        // swaybg takes a color instead of a file in solid_color mode
+        let bg = match wallpaper.option() {
+            PictureOptions::None => wallpaper.colors().0.to_string(),
+            _ => wallpaper.target(base, prefix)?.display().to_string(),
+        };
+        ret.insert(
+            wallpaper.id(),
+            format!(
+                "# {}\noutput * bg {} {}\n",
+                wallpaper.id(),
+                bg,
+                wallpaper.option().to_swaybg_mode()
+            ),
+        );
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::render_sway;
+    use crate::meta::Metadata;
+
+    #[test]
+    fn test_render() {
+        let dummy_meta = toml::from_str::<Metadata>(crate::meta::test::DUMMY_META).unwrap();
+        let result =
+            render_sway(&dummy_meta, &PathBuf::from("."), Path::new("/usr/share")).unwrap();
+        assert_eq!(
+            result.get("Kusa").unwrap(),
+            r#"# Kusa
+output * bg /usr/share/wallpapers/Kusa/contents/images/7680x4320.jpg tile
+"#
+        );
+    }
+}
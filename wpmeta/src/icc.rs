@@ -0,0 +1,85 @@
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::io::Reader;
+use image::{ImageDecoder, ImageFormat};
+use log::debug;
+
+use std::path::Path;
+
+/// Identifies the APP2 segments of a JPEG that carry an ICC profile.
+static ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+/// Room for the profile in one segment, after the length, the marker and
+/// the chunk numbers.
+const CHUNK_SIZE: usize = u16::MAX as usize - 2 - ICC_MARKER.len() - 2;
+
+/// Reads the ICC profile embedded in the image at `path`. Only JPEG, PNG
+/// and WebP sources are looked at.
+pub fn read(path: &Path) -> Option<Vec<u8>> {
+    let reader = Reader::open(path).ok()?.with_guessed_format().ok()?;
+    let format = reader.format()?;
+    let reader = reader.into_inner();
+    let ret = match format {
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    };
+    if ret.is_some() {
+        debug!("{}: found an ICC profile", path.display());
+    }
+    ret
+}
+
+/// Embeds `profile` into the encoded `jpeg`, split across as many APP2
+/// segments as needed.
+pub fn embed_jpeg(jpeg: &[u8], profile: &[u8]) -> Vec<u8> {
+    // Segments go after SOI and the JFIF header, which must come first
+    let mut offset = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        offset += 2 + usize::from(u16::from_be_bytes([jpeg[4], jpeg[5]]));
+    }
+    let chunks: Vec<_> = profile.chunks(CHUNK_SIZE).collect();
+    let mut ret = Vec::with_capacity(jpeg.len() + profile.len() + chunks.len() * 18);
+    ret.extend_from_slice(&jpeg[..offset]);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let len = (2 + ICC_MARKER.len() + 2 + chunk.len()) as u16;
+        ret.extend_from_slice(&[0xFF, 0xE2]);
+        ret.extend_from_slice(&len.to_be_bytes());
+        ret.extend_from_slice(ICC_MARKER);
+        // Chunks are numbered from 1
+        ret.extend_from_slice(&[i as u8 + 1, chunks.len() as u8]);
+        ret.extend_from_slice(chunk);
+    }
+    ret.extend_from_slice(&jpeg[offset..]);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+
+    use std::fs;
+    use std::io::Cursor;
+
+    use super::{embed_jpeg, read};
+
+    #[test]
+    fn test_embed_jpeg() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(64, 36))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kusa.jpg");
+        fs::write(&path, &jpeg).unwrap();
+        assert_eq!(read(&path), None);
+
+        // Large enough to take more than one segment
+        let profile: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let embedded = embed_jpeg(&jpeg, &profile);
+        fs::write(&path, &embedded).unwrap();
+        assert_eq!(read(&path), Some(profile));
+        assert!(image::load_from_memory(&embedded).is_ok());
+    }
+}
@@ -0,0 +1,174 @@
+use eyre::{bail, Result};
+use image::{GenericImageView, ImageFormat};
+use log::info;
+use toml::Value;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::meta::WallpaperKind;
+use crate::{orientation, palette};
+
+/// Images of a new wallpaper, named after the stem of the normal variant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Candidate {
+    id: String,
+    normal: PathBuf,
+    dark: Option<PathBuf>,
+}
+
+/// Strips the `dark` suffix and the separator before it from `stem`.
+fn strip_dark(stem: &str) -> &str {
+    stem[..stem.len() - "dark".len()].trim_end_matches(['-', '_', '.', ' '])
+}
+
+/// Groups the images directly in `dir`, pairing every dark variant with
+/// the normal image of the same name.
+fn candidates(dir: &Path) -> Result<Vec<Candidate>> {
+    let mut images = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && ImageFormat::from_path(&path).is_ok() {
+            images.push(path);
+        }
+    }
+    images.sort();
+    let stem = |p: &Path| {
+        p.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    let (darks, normals): (Vec<_>, Vec<_>) = images
+        .into_iter()
+        .partition(|p| WallpaperKind::from_file_name(p) == WallpaperKind::Dark);
+    let mut ret: Vec<_> = normals
+        .into_iter()
+        .map(|normal| Candidate {
+            id: stem(&normal),
+            normal,
+            dark: None,
+        })
+        .collect();
+    for dark in darks {
+        let name = stem(&dark);
+        match ret.iter_mut().find(|c| c.id == strip_dark(&name)) {
+            Some(candidate) if candidate.dark.is_none() => candidate.dark = Some(dark),
+            // Without a normal variant the image is a wallpaper of its own
+            _ => ret.push(Candidate {
+                id: name,
+                normal: dark,
+                dark: None,
+            }),
+        }
+    }
+    ret.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(ret)
+}
+
+/// Quotes `s` as a TOML string.
+fn quote(s: &str) -> String {
+    Value::String(s.to_owned()).to_string()
+}
+
+/// Name of `path` relative to the scanned directory.
+fn file_name(path: &Path) -> String {
+    quote(&path.file_name().unwrap_or_default().to_string_lossy())
+}
+
+/// Renders a starter manifest for the images directly in `dir`, with
+/// placeholders for what can't be detected.
+pub fn scaffold(dir: &Path) -> Result<String> {
+    let candidates = candidates(dir)?;
+    if candidates.is_empty() {
+        bail!("no images found in {}", dir.display());
+    }
+    let mut ret = String::from(
+        r#"[[authors]]
+email = "author@example.com"
+name.default = "Author"
+"#,
+    );
+    for candidate in candidates {
+        let img = orientation::open(&candidate.normal)?;
+        let (width, height) = img.dimensions();
+        let (primary, secondary) = palette::extract_colors(&img)?;
+        let path = match &candidate.dark {
+            Some(dark) => format!("[{}, {}]", file_name(&candidate.normal), file_name(dark)),
+            None => file_name(&candidate.normal),
+        };
+        let id = quote(&candidate.id);
+        write!(
+            ret,
+            r#"
+# {width}x{height}
+[[wallpapers]]
+id = {id}
+title.default = {id}
+license = "CC-BY-SA-4.0"
+path = {path}
+primary_color = "{primary}"
+secondary_color = "{secondary}"
+"#
+        )?;
+    }
+    Ok(ret)
+}
+
+/// Writes a starter `metadata.toml` for the images in `dir`.
+pub fn init(dir: &Path, force: bool) -> Result<()> {
+    let target = dir.join("metadata.toml");
+    if target.exists() && !force {
+        bail!("{} already exists", target.display());
+    }
+    let manifest = scaffold(dir)?;
+    info!("writing {}", target.display());
+    fs::write(target, manifest)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use image::RgbImage;
+
+    use std::fs;
+
+    use super::init;
+    use crate::meta::Metadata;
+
+    #[test]
+    fn test_init() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(init(dir.path(), false).is_err());
+        for (name, pixel) in [
+            ("kusa.png", [2, 60, 136]),
+            ("kusa-dark.png", [0, 0, 0]),
+            ("hana.jpg", [240, 120, 16]),
+        ] {
+            RgbImage::from_pixel(64, 36, image::Rgb(pixel))
+                .save(dir.path().join(name))
+                .unwrap();
+        }
+        fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+        init(dir.path(), false).unwrap();
+
+        let manifest = fs::read_to_string(dir.path().join("metadata.toml")).unwrap();
+        let meta = toml::from_str::<Metadata>(&manifest).unwrap();
+        let wallpapers = meta.wallpapers().unwrap();
+        assert_eq!(wallpapers.len(), 2);
+        assert_eq!(wallpapers[0].id(), "hana");
+        assert_eq!(wallpapers[0].dark_src(), None);
+        assert_eq!(wallpapers[1].id(), "kusa");
+        assert_eq!(wallpapers[1].src().to_str(), Some("kusa.png"));
+        assert_eq!(
+            wallpapers[1].dark_src().unwrap().to_str(),
+            Some("kusa-dark.png")
+        );
+        assert!(manifest.contains("# 64x36\n"));
+        assert!(manifest.contains(r##"primary_color = "#023C88""##));
+        // An existing manifest is kept unless forced
+        assert!(init(dir.path(), false).is_err());
+        assert!(init(dir.path(), true).is_ok());
+    }
+}
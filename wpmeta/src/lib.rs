@@ -0,0 +1,33 @@
+//! Generates desktop wallpaper metadata from a tree of `metadata.toml`
+//! files. [`generate_all`] runs the same generation as the command line,
+//! e.g. from a build script.
+
+// Lets the test fixtures shared with the binary and integration tests name
+// the crate the same way everywhere
+#[cfg(test)]
+extern crate self as wpmeta;
+
+pub mod color;
+pub mod config;
+pub mod doctor;
+pub mod error;
+pub mod fmt;
+pub mod generate;
+pub mod icc;
+pub mod init;
+pub mod meta;
+pub mod orientation;
+pub mod palette;
+pub mod paths;
+pub mod pipeline;
+pub mod po;
+pub mod preview;
+pub mod progress;
+pub mod stamp;
+pub mod validate;
+pub mod walk;
+
+#[cfg(test)]
+pub mod test;
+
+pub use pipeline::{generate_all, GenerateOptions, Generation};
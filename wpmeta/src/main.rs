@@ -1,156 +1,1149 @@
-pub mod generate;
-pub mod meta;
-pub mod walk;
-
-use clap::Parser;
-use eyre::{bail, Result, WrapErr};
-use image::imageops::FilterType;
-use image::io::Reader as ImageReader;
-use image::ImageFormat;
-use log::{debug, info};
-use rayon::prelude::*;
-
-use std::fs::{copy, create_dir_all, remove_file, File};
-use std::io::Write;
-use std::os::unix::fs::symlink;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use eyre::{bail, Result};
+use log::info;
+
+use std::env;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 
-use generate::{render_gnome, render_kde};
-use meta::Metadata;
+use wpmeta::pipeline::{self, write_file, GenerateOptions};
+use wpmeta::{config, doctor, fmt, init, progress, validate};
 
-static MATE_META_BASE: &str = "usr/share/mate-background-properties";
-static GNOME_META_BASE: &str = "usr/share/gnome-background-properties";
-static KDE_META_BASE: &str = "usr/share/wallpapers";
+#[cfg(test)]
+#[path = "test.rs"]
+mod fixtures;
 
 #[derive(Parser)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Option<Args>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check the environment for likely problems
+    Doctor {
+        #[arg(short, long)]
+        src: PathBuf,
+        #[arg(short, long)]
+        dst: PathBuf,
+    },
+    /// Rewrite every metadata.toml in a tree in a canonical layout
+    Fmt {
+        dir: PathBuf,
+        /// Only report manifests that would change, failing if any would
+        #[arg(long)]
+        check: bool,
+    },
+    /// Write a starter metadata.toml for the images in a directory
+    Init {
+        dir: PathBuf,
+        /// Replace an existing metadata.toml
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check a source tree without generating anything
+    Validate {
+        #[arg(short, long)]
+        src: PathBuf,
+    },
+}
+
+#[derive(clap::Args)]
 pub struct Args {
-    #[arg(short, long)]
+    // clap leaves the group of a struct with flattened fields empty, so
+    // join it explicitly for `Cli::args` to be detected
+    #[arg(short, long, group = "Args")]
     src: PathBuf,
-    #[arg(short, long)]
+    #[arg(short, long, group = "Args")]
     dst: PathBuf,
+    #[command(flatten)]
+    options: GenerateOptions,
+    /// Write a JSON report of the generated files to this path
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Write a JSON catalog of all wallpapers to this path
+    #[arg(long)]
+    catalog: Option<PathBuf>,
 }
 
-fn ensure_dir(dir: &Path) -> Result<()> {
-    if !dir.is_dir() {
-        debug!("creating directory at {}", dir.display());
-        create_dir_all(dir)?;
+fn run(args: &Args) -> Result<()> {
+    let options = &args.options;
+    let generation = pipeline::generate_all(&args.src, &args.dst, options)?;
+    if let Some(path) = &args.report {
+        info!("writing report to {}", path.display());
+        write_file(
+            path,
+            generation.report.to_json()?.as_bytes(),
+            options.overwrite_policy,
+            options.force,
+        )?;
+    }
+    if let Some(path) = &args.catalog {
+        info!("writing catalog to {}", path.display());
+        write_file(
+            path,
+            generation.catalog.to_json()?.as_bytes(),
+            options.overwrite_policy,
+            options.force,
+        )?;
     }
     Ok(())
 }
 
-fn ensure_parent(file: &Path) -> Result<()> {
-    if let Some(parent) = file.parent() {
-        ensure_dir(parent)
-    } else {
-        bail!("invalid path");
+/// Prints `findings`, failing if any of them did.
+fn report_findings(findings: &[doctor::Finding]) -> Result<()> {
+    for finding in findings {
+        println!("{}", finding);
+    }
+    let failed = findings.iter().filter(|f| !f.ok).count();
+    if failed > 0 {
+        bail!("{} problem(s) found", failed);
     }
+    Ok(())
 }
 
-fn write_file(target: &Path, content: &[u8]) -> Result<()> {
-    ensure_parent(target)?;
-    debug!("writing to {}", target.display());
-    let mut f = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(target)?;
-    f.write_all(content)?;
+/// Formats the manifests in `dir`, failing if any is unformatted in
+/// `check` mode.
+fn format_tree(dir: &Path, check: bool) -> Result<()> {
+    let changed = fmt::fmt(dir, check)?;
+    if check {
+        for file in &changed {
+            println!("{}", file.display());
+        }
+        if !changed.is_empty() {
+            bail!("{} manifest(s) not formatted", changed.len());
+        }
+    }
     Ok(())
 }
 
-fn generate_preview(src: &Path, target: &Path) -> Result<()> {
-    let img = ImageReader::open(src)?.decode()?;
-    let img = img.resize(500, 500, FilterType::Lanczos3);
-    ensure_parent(target)?;
-    img.save_with_format(target, ImageFormat::Jpeg)?;
-    Ok(())
+/// Parses `argv`, taking generation options it doesn't give from the config
+/// files of the source tree and `config_home`, in that order.
+fn parse_cli(argv: Vec<OsString>, config_home: Option<&Path>) -> Result<Cli> {
+    let matches = Cli::command().get_matches_from(&argv);
+    let src = matches
+        .get_one::<PathBuf>("src")
+        .filter(|_| matches.subcommand().is_none());
+    let Some(src) = src else {
+        return Ok(Cli::from_arg_matches(&matches)?);
+    };
+    let config = config::load(src, config_home)?;
+    let defaults = config::to_args(&Cli::command(), &matches, &config)?;
+    let mut argv = argv.into_iter();
+    let argv: Vec<_> = argv
+        .next()
+        .into_iter()
+        .chain(defaults)
+        .chain(argv)
+        .collect();
+    Ok(Cli::from_arg_matches(
+        &Cli::command().get_matches_from(argv),
+    )?)
 }
 
-fn copy_file(src: &Path, dst: &Path) -> Result<()> {
-    if !src.is_file() {
-        bail!("src {} is not a file", src.display());
-    }
-    if let Some(parent) = dst.parent() {
-        ensure_dir(parent)?;
-    } else {
-        bail!("invalid destination {}", dst.display());
+fn main() -> Result<()> {
+    progress::init_logger("WPMETA_LOG")?;
+    let cli = parse_cli(env::args_os().collect(), config::config_home().as_deref())?;
+    match (cli.command, cli.args) {
+        (Some(Command::Doctor { src, dst }), _) => report_findings(&doctor::doctor(&src, &dst)),
+        (Some(Command::Fmt { dir, check }), _) => format_tree(&dir, check),
+        (Some(Command::Init { dir, force }), _) => init::init(&dir, force),
+        (Some(Command::Validate { src }), _) => report_findings(&validate::validate(&src)),
+        (None, Some(args)) => run(&args),
+        // clap requires the generation arguments without a subcommand
+        (None, None) => unreachable!(),
     }
-    debug!("copying {} to {}", src.display(), dst.display());
-    copy(src, dst)?;
-    Ok(())
 }
 
-fn process_meta(meta: Metadata, dst: &Path) -> Result<()> {
-    info!("processing meta at {:?}", meta.base());
-    let cur = PathBuf::from(".");
-    let base = meta.base().unwrap_or(&cur);
-    let gnome_metas = render_gnome(&meta, base)?;
-    let kde_metas = render_kde(&meta)?;
-    for wallpaper in meta.wallpapers().unwrap() {
-        let id = wallpaper.id();
-        let src = base.join(wallpaper.src());
-        let target = wallpaper.target(base);
-        let gnome_meta = gnome_metas.get(id).unwrap();
-        let kde_meta = kde_metas.get(id).unwrap();
-
-        info!("{}: writing metadata", id);
-        let gnome_meta_file = format!("{}.xml", id);
-        write_file(
-            &dst.join(GNOME_META_BASE).join(&gnome_meta_file),
-            gnome_meta.as_bytes(),
-        )?;
-        write_file(
-            &dst.join(KDE_META_BASE).join(id).join("metadata.json"),
-            kde_meta.as_bytes(),
-        )?;
-        // Generate symlink for MATE
-        let mate_meta_path = dst.join(MATE_META_BASE).join(&gnome_meta_file);
-        if mate_meta_path.read_link().is_ok() {
-            remove_file(&mate_meta_path)?;
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+    use image::RgbImage;
+    use wpmeta::generate::Target;
+    use wpmeta::meta::Resolution;
+    use wpmeta::paths::{DEFAULT_PREFIX, KDE_META_BASE};
+    use wpmeta::paths::{GNOME_META_BASE, MATE_META_BASE};
+    use wpmeta::pipeline::clean_outputs;
+
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{parse_cli, run, Args, Cli};
+    use crate::fixtures::{dummy_tree, write_dummy_dir, DUMMY_TREE_META};
+
+    pub fn parse_args(src: &Path, dst: &Path, extra: &[&str]) -> Args {
+        let mut args = vec![
+            "wpmeta",
+            "--src",
+            src.to_str().unwrap(),
+            "--dst",
+            dst.to_str().unwrap(),
+        ];
+        args.extend_from_slice(extra);
+        Cli::parse_from(args).args.unwrap()
+    }
+
+    #[test]
+    fn test_targets() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        run(&parse_args(src.path(), dst.path(), &["--targets", "kde"])).unwrap();
+        let kde_dir = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa");
+        assert!(kde_dir.join("metadata.json").is_file());
+        assert!(kde_dir.join("contents/screenshot.jpg").is_file());
+        assert!(kde_dir.join("contents/images/64x36.png").is_file());
+        assert!(!dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(GNOME_META_BASE)
+            .exists());
+        assert!(!dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(MATE_META_BASE)
+            .exists());
+    }
+
+    #[test]
+    fn test_report() {
+        let src = dummy_tree(
+            r##"
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.default = "Yajuu Senpai"
+
+            [[wallpapers]]
+            title.default = "Kusa"
+            license = "CC-BY-SA-4.0"
+            id = "Kusa"
+            path = "kusa.png"
+
+            [[wallpapers]]
+            title.default = "Hana"
+            license = "MIT"
+            id = "Hana"
+            path = "hana.png"
+            primary_color = "#FF0000"
+            "##,
+        );
+        let dst = tempfile::tempdir().unwrap();
+        let report_path = dst.path().join("report.json");
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &[
+                "--targets",
+                "kde,gnome",
+                "--report",
+                report_path.to_str().unwrap(),
+            ],
+        ))
+        .unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(report_path).unwrap()).unwrap();
+        let kusa = &report["Kusa"];
+        assert_eq!(
+            kusa["manifests"],
+            serde_json::json!([
+                "usr/share/gnome-background-properties/Kusa.xml",
+                "usr/share/wallpapers/Kusa/metadata.json"
+            ])
+        );
+        assert_eq!(
+            kusa["images"],
+            serde_json::json!([{
+                "path": "usr/share/wallpapers/Kusa/contents/images/64x36.png",
+                "width": 64,
+                "height": 36
+            }])
+        );
+        assert_eq!(
+            kusa["preview"],
+            "usr/share/wallpapers/Kusa/contents/screenshot.jpg"
+        );
+        assert_eq!(kusa["license"], "CC-BY-SA-4.0");
+        assert_eq!(kusa["primary_color"], "#023C88");
+        assert_eq!(kusa["secondary_color"], "#5789CA");
+        let hana = &report["Hana"];
+        assert_eq!(hana["license"], "MIT");
+        assert_eq!(hana["primary_color"], "#FF0000");
+        assert_eq!(hana["manifests"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_catalog() {
+        let src = dummy_tree(&format!(
+            "{}{}",
+            DUMMY_TREE_META,
+            r#"
+            title.zh-CN = "草"
+
+            [[wallpapers]]
+            title.default = "Hana"
+            title.ja-JP = "花"
+            license = "MIT"
+            id = "Hana"
+            path = "hana.png"
+            "#
+        ));
+        let dst = tempfile::tempdir().unwrap();
+        let catalog_path = dst.path().join("wallpapers.json");
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &[
+                "--targets",
+                "gnome",
+                "--catalog",
+                catalog_path.to_str().unwrap(),
+            ],
+        ))
+        .unwrap();
+        let catalog: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(catalog_path).unwrap()).unwrap();
+        assert_eq!(
+            catalog,
+            serde_json::json!([
+                {
+                    "id": "Hana",
+                    "title": { "default": "Hana", "ja-JP": "花" },
+                    "authors": [{
+                        "email": "yajuu.senpai@example.com",
+                        "name": { "default": "Yajuu Senpai" }
+                    }],
+                    "license": "MIT",
+                    "primary_color": "#023C88",
+                    "secondary_color": "#5789CA",
+                    "image": "usr/share/wallpapers/Hana/contents/images/64x36.png",
+                    "preview": null
+                },
+                {
+                    "id": "Kusa",
+                    "title": { "default": "Kusa", "zh-CN": "草" },
+                    "authors": [{
+                        "email": "yajuu.senpai@example.com",
+                        "name": { "default": "Yajuu Senpai" }
+                    }],
+                    "license": "CC BY-SA 4.0",
+                    "primary_color": "#023C88",
+                    "secondary_color": "#5789CA",
+                    "image": "usr/share/wallpapers/Kusa/contents/images/64x36.png",
+                    "preview": null
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_clean() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let stale = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(GNOME_META_BASE)
+            .join("OldId.xml");
+        let stale_image = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("OldId/contents/images/1x1.png");
+        let user_file = dst.path().join("usr/share/backgrounds/mine.png");
+        for path in [&stale, &stale_image, &user_file] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &["--clean", "--targets", "gnome"],
+        ))
+        .unwrap();
+        assert!(!stale.exists());
+        assert!(!stale_image.exists());
+        assert!(user_file.exists());
+        assert!(dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(GNOME_META_BASE)
+            .join("Kusa.xml")
+            .exists());
+
+        // Nothing to remove
+        let empty = tempfile::tempdir().unwrap();
+        clean_outputs(empty.path(), Path::new(DEFAULT_PREFIX), &Target::all()).unwrap();
+    }
+
+    #[test]
+    fn test_dedup() {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = format!(
+            "{}{}",
+            DUMMY_TREE_META,
+            r#"
+            [[wallpapers]]
+            title.default = "Kusa Again"
+            license = "CC BY-SA 4.0"
+            id = "KusaAgain"
+            path = "kusa.png"
+            "#
+        );
+        let src = dummy_tree(&meta);
+        let dst = tempfile::tempdir().unwrap();
+        let images = |dst: &Path, id: &str| {
+            dst.join(DEFAULT_PREFIX)
+                .join(KDE_META_BASE)
+                .join(id)
+                .join("contents/images/64x36.png")
+        };
+        let args = parse_args(src.path(), dst.path(), &["--dedup", "--targets", "gnome"]);
+        // A second run must relink over the existing files
+        for _ in 0..2 {
+            run(&args).unwrap();
+            let a = fs::metadata(images(dst.path(), "Kusa")).unwrap();
+            let b = fs::metadata(images(dst.path(), "KusaAgain")).unwrap();
+            assert_eq!(a.ino(), b.ino());
+            assert_eq!(a.nlink(), 2);
+        }
+
+        let dst = tempfile::tempdir().unwrap();
+        let args = parse_args(src.path(), dst.path(), &["--targets", "gnome"]);
+        run(&args).unwrap();
+        let a = fs::metadata(images(dst.path(), "Kusa")).unwrap();
+        assert_eq!(a.nlink(), 1);
+    }
+
+    #[test]
+    fn test_dedup_rerun() {
+        let meta = format!(
+            "{}{}",
+            DUMMY_TREE_META,
+            r#"
+            [[wallpapers]]
+            title.default = "Hana"
+            license = "CC BY-SA 4.0"
+            id = "Hana"
+            path = "hana.png"
+            "#
+        );
+        let src = dummy_tree(&meta);
+        let dst = tempfile::tempdir().unwrap();
+        let image = |id: &str| {
+            fs::read(
+                dst.path()
+                    .join(DEFAULT_PREFIX)
+                    .join(KDE_META_BASE)
+                    .join(id)
+                    .join("contents/images/64x36.png"),
+            )
+            .unwrap()
+        };
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &["--dedup", "--targets", "gnome"],
+        ))
+        .unwrap();
+        let kusa = fs::read(src.path().join("kusa.png")).unwrap();
+        assert_eq!(image("Hana"), kusa);
+        // The copies were hardlinked, a new Hana must not end up in Kusa
+        image::RgbImage::from_pixel(64, 36, image::Rgb([255, 0, 0]))
+            .save(src.path().join("hana.png"))
+            .unwrap();
+        run(&parse_args(src.path(), dst.path(), &["--targets", "gnome"])).unwrap();
+        assert_eq!(image("Kusa"), kusa);
+        assert_eq!(
+            image("Hana"),
+            fs::read(src.path().join("hana.png")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_symlink_images() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let wallpaper = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa/contents/images/64x36.png");
+        let source = src.path().join("kusa.png").canonicalize().unwrap();
+        for (style, absolute) in [("absolute", true), ("relative", false)] {
+            let flag = format!("--symlink-images={}", style);
+            run(&parse_args(src.path(), dst.path(), &[&flag])).unwrap();
+            let target = wallpaper.read_link().unwrap();
+            assert_eq!(target.is_absolute(), absolute);
+            let resolved = wallpaper.parent().unwrap().join(target);
+            assert_eq!(resolved.canonicalize().unwrap(), source);
         }
-        ensure_parent(&mate_meta_path)?;
-        symlink(
-            PathBuf::from("/")
+        // Manifests keep pointing at the destination
+        let xml = fs::read_to_string(
+            dst.path()
+                .join(DEFAULT_PREFIX)
+                .join("gnome-background-properties/Kusa.xml"),
+        )
+        .unwrap();
+        assert!(xml.contains("/wallpapers/Kusa/contents/images/64x36.png"));
+        // A later copy replaces the link rather than writing through it
+        run(&parse_args(src.path(), dst.path(), &[])).unwrap();
+        assert!(!wallpaper.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read(&wallpaper).unwrap(), fs::read(&source).unwrap());
+    }
+
+    #[test]
+    fn test_deref_sources() {
+        use std::os::unix::fs::symlink;
+
+        let src = dummy_tree(DUMMY_TREE_META);
+        fs::rename(src.path().join("kusa.png"), src.path().join("real.png")).unwrap();
+        symlink("real.png", src.path().join("kusa.png")).unwrap();
+        let real = fs::read(src.path().join("real.png")).unwrap();
+        let source = src.path().canonicalize().unwrap();
+        for deref in ["true", "false"] {
+            let flag = format!("--deref-sources={}", deref);
+            let dst = tempfile::tempdir().unwrap();
+            let wallpaper = dst
+                .path()
+                .join(DEFAULT_PREFIX)
+                .join(KDE_META_BASE)
+                .join("Kusa/contents/images/64x36.png");
+            // Copies hold the image either way
+            run(&parse_args(src.path(), dst.path(), &[&flag])).unwrap();
+            assert!(!wallpaper.symlink_metadata().unwrap().is_symlink());
+            assert_eq!(fs::read(&wallpaper).unwrap(), real);
+
+            let args = ["--symlink-images=absolute", &flag];
+            run(&parse_args(src.path(), dst.path(), &args)).unwrap();
+            let expected = match deref {
+                "true" => source.join("real.png"),
+                _ => source.join("kusa.png"),
+            };
+            assert_eq!(wallpaper.read_link().unwrap(), expected);
+            assert_eq!(fs::read(&wallpaper).unwrap(), real);
+        }
+    }
+
+    #[test]
+    fn test_stamp() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let prefix = |dst: &Path| dst.join(DEFAULT_PREFIX);
+        let manifests = |dst: &Path| {
+            [
+                prefix(dst).join(KDE_META_BASE).join("Kusa/metadata.json"),
+                prefix(dst).join("gnome-background-properties/Kusa.xml"),
+            ]
+            .map(|path| fs::read_to_string(path).unwrap())
+        };
+        let dst = tempfile::tempdir().unwrap();
+        run(&parse_args(src.path(), dst.path(), &[])).unwrap();
+        for manifest in manifests(dst.path()) {
+            assert!(!manifest.contains("Generated"));
+        }
+        run(&parse_args(src.path(), dst.path(), &["--stamp"])).unwrap();
+        let [kde, gnome] = manifests(dst.path());
+        let version = format!("wpmeta {} at ", env!("CARGO_PKG_VERSION"));
+        assert!(kde.contains(&format!(r#""GeneratedBy": "{}"#, version)));
+        assert!(gnome.contains(&format!("<!-- Generated by {}", version)));
+    }
+
+    #[test]
+    fn test_reproducible() {
+        fn contents(dir: &Path, base: &Path, ret: &mut Vec<(PathBuf, Vec<u8>)>) {
+            let mut entries: Vec<_> = fs::read_dir(dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect();
+            entries.sort();
+            for path in entries {
+                let name = path.strip_prefix(base).unwrap().to_owned();
+                // Links point at installed paths, so compare their targets
+                if path.is_symlink() {
+                    let target = fs::read_link(&path).unwrap();
+                    ret.push((name, target.into_os_string().into_encoded_bytes()));
+                } else if path.is_dir() {
+                    contents(&path, base, ret);
+                } else {
+                    ret.push((name, fs::read(&path).unwrap()));
+                }
+            }
+        }
+        let src = dummy_tree(DUMMY_TREE_META);
+        write_dummy_dir(
+            &src.path().join("hana"),
+            &DUMMY_TREE_META.replace("Kusa", "Hana"),
+        );
+        let outputs = [(); 2].map(|_| {
+            let dst = tempfile::tempdir().unwrap();
+            let flags = ["--reproducible", "--stamp"];
+            run(&parse_args(src.path(), dst.path(), &flags)).unwrap();
+            let mut ret = Vec::new();
+            contents(dst.path(), dst.path(), &mut ret);
+            ret
+        });
+        assert!(outputs[0].len() > 4);
+        assert_eq!(outputs[0], outputs[1]);
+        assert!(outputs[0]
+            .iter()
+            .all(|(_, bytes)| !String::from_utf8_lossy(bytes).contains("Generated")));
+    }
+
+    #[test]
+    fn test_preview_only() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let kde_dir = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa");
+        run(&parse_args(src.path(), dst.path(), &[])).unwrap();
+        let manifest = kde_dir.join("metadata.json");
+        let mtime = fs::metadata(&manifest).unwrap().modified().unwrap();
+        fs::remove_dir_all(kde_dir.join("contents/images")).unwrap();
+
+        let flags = ["--preview-only", "--preview-resolution-limit", "32x18"];
+        run(&parse_args(src.path(), dst.path(), &flags)).unwrap();
+        let screenshot = image::open(kde_dir.join("contents/screenshot.jpg")).unwrap();
+        assert_eq!((screenshot.width(), screenshot.height()), (32, 18));
+        assert_eq!(fs::metadata(&manifest).unwrap().modified().unwrap(), mtime);
+        assert!(!kde_dir.join("contents/images").exists());
+        // A clean run would leave nothing to preview against
+        let flags = ["--preview-only", "--clean"];
+        assert!(Cli::try_parse_from(
+            ["wpmeta", "--src", "a", "--dst", "b"]
+                .into_iter()
+                .chain(flags)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_license_file() {
+        let license = |src: &Path| {
+            let dst = tempfile::tempdir().unwrap();
+            run(&parse_args(src, dst.path(), &[])).unwrap();
+            let path = dst
+                .path()
+                .join(DEFAULT_PREFIX)
+                .join(KDE_META_BASE)
+                .join("Kusa/LICENSE.txt");
+            fs::read_to_string(path).ok()
+        };
+        assert_eq!(license(dummy_tree(DUMMY_TREE_META).path()), None);
+        let meta = DUMMY_TREE_META.replace(
+            "license = \"CC BY-SA 4.0\"",
+            r#"license = "CC-BY-SA-4.0"
+    license_url = "https://creativecommons.org/licenses/by-sa/4.0/"
+    attribution.default = "Photo by Yajuu Senpai"
+    attribution."zh-CN" = "摄影：Yajuu Senpai""#,
+        );
+        assert_eq!(
+            license(dummy_tree(&meta).path()).unwrap(),
+            "SPDX-License-Identifier: CC-BY-SA-4.0\n\
+             License-URL: https://creativecommons.org/licenses/by-sa/4.0/\n\
+             \n\
+             Photo by Yajuu Senpai\n"
+        );
+        // The license is written in its canonical form
+        let meta = meta.replace("CC-BY-SA-4.0", "GPL-2.0+");
+        assert!(license(dummy_tree(&meta).path())
+            .unwrap()
+            .starts_with("SPDX-License-Identifier: GPL-2.0-or-later\n"));
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        let src = dummy_tree(&DUMMY_TREE_META.replace("kusa.png", "kusa.jpg"));
+        let jpeg = fs::read(src.path().join("kusa.jpg")).unwrap();
+        let profile = b"not really an ICC profile".to_vec();
+        fs::write(
+            src.path().join("kusa.jpg"),
+            wpmeta::icc::embed_jpeg(&jpeg, &profile),
+        )
+        .unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        run(&parse_args(src.path(), dst.path(), &["--targets", "kde"])).unwrap();
+        let contents = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa/contents");
+        let screenshot = contents.join("screenshot.jpg");
+        assert_eq!(wpmeta::icc::read(&screenshot), Some(profile.clone()));
+        // Copies are byte for byte, so they keep it too
+        assert_eq!(
+            wpmeta::icc::read(&contents.join("images/64x36.jpg")),
+            Some(profile)
+        );
+    }
+
+    #[test]
+    fn test_webp() {
+        let src = dummy_tree(&DUMMY_TREE_META.replace("kusa.png", "kusa.webp"));
+        let dst = tempfile::tempdir().unwrap();
+        let args = parse_args(
+            src.path(),
+            dst.path(),
+            &["--targets", "kde", "--preview-format", "webp"],
+        );
+        run(&args).unwrap();
+        let kde_dir = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa");
+        assert!(kde_dir.join("contents/images/64x36.webp").is_file());
+        let preview = kde_dir.join("contents/screenshot.webp");
+        let format = image::io::Reader::open(&preview)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .format();
+        assert_eq!(format, Some(image::ImageFormat::WebP));
+    }
+
+    #[test]
+    fn test_blurhash() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let read_hash = |extra: &[&str]| {
+            let dst = tempfile::tempdir().unwrap();
+            let mut args = vec!["--targets", "kde"];
+            args.extend_from_slice(extra);
+            run(&parse_args(src.path(), dst.path(), &args)).unwrap();
+            let path = dst
+                .path()
+                .join(DEFAULT_PREFIX)
+                .join(KDE_META_BASE)
+                .join("Kusa/metadata.json");
+            let json: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+            json.get("BlurHash").map(|h| h.as_str().unwrap().to_owned())
+        };
+        assert_eq!(read_hash(&[]), None);
+        let hash = read_hash(&["--blurhash"]).unwrap();
+        assert_eq!(
+            blurhash::decode(&hash, 16, 9, 1.0).unwrap().len(),
+            16 * 9 * 4
+        );
+    }
+
+    #[test]
+    fn test_dark_variant() {
+        let meta = DUMMY_TREE_META.replace(
+            r#"path = "kusa.png""#,
+            r#"path = ["kusa.png", "kusa-dark.png"]"#,
+        );
+        let src = dummy_tree(&meta);
+        let dst = tempfile::tempdir().unwrap();
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &["--targets", "kde,gnome"],
+        ))
+        .unwrap();
+        let dark = "usr/share/wallpapers/Kusa/contents/images_dark/64x36.png";
+        assert!(dst.path().join(dark).is_file());
+        let xml = fs::read_to_string(
+            dst.path()
+                .join(DEFAULT_PREFIX)
                 .join(GNOME_META_BASE)
-                .join(&gnome_meta_file),
-            mate_meta_path,
-        )?;
+                .join("Kusa.xml"),
+        )
+        .unwrap();
+        assert!(xml.contains(&format!("<filename-dark>/{}</filename-dark>", dark)));
+    }
 
-        let wallpaper_dst = dst.join(target);
-        info!(
-            "{}: copying wallpaper file {} -> {}",
-            id,
-            src.display(),
-            wallpaper_dst.display()
+    #[test]
+    fn test_jobs() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        for jobs in ["0", "1", "4"] {
+            let dst = tempfile::tempdir().unwrap();
+            let args = parse_args(
+                src.path(),
+                dst.path(),
+                &["--jobs", jobs, "--targets", "gnome"],
+            );
+            run(&args).unwrap();
+            assert!(dst
+                .path()
+                .join(DEFAULT_PREFIX)
+                .join(GNOME_META_BASE)
+                .join("Kusa.xml")
+                .is_file());
+        }
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let src = tempfile::tempdir().unwrap();
+        write_dummy_dir(&src.path().join("a"), DUMMY_TREE_META);
+        write_dummy_dir(&src.path().join("b"), DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let err = run(&parse_args(src.path(), dst.path(), &["--targets", "gnome"])).unwrap_err();
+        assert!(format!("{:?}", err).contains("duplicate wallpaper id Kusa"));
+    }
+
+    #[test]
+    fn test_prefix() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let args = parse_args(
+            src.path(),
+            dst.path(),
+            &["--prefix", "usr/local/share", "--targets", "gnome,mate,kde"],
+        );
+        run(&args).unwrap();
+        let prefix = dst.path().join("usr/local/share");
+        assert!(prefix
+            .join("wallpapers/Kusa/contents/images/64x36.png")
+            .is_file());
+        assert!(prefix.join("wallpapers/Kusa/metadata.json").is_file());
+        let xml = fs::read_to_string(prefix.join(GNOME_META_BASE).join("Kusa.xml")).unwrap();
+        assert!(xml.contains(
+            "<filename>/usr/local/share/wallpapers/Kusa/contents/images/64x36.png</filename>"
+        ));
+        let mate = fs::read_link(prefix.join(MATE_META_BASE).join("Kusa.xml")).unwrap();
+        assert_eq!(
+            mate,
+            Path::new("/usr/local/share/gnome-background-properties/Kusa.xml")
         );
-        copy_file(&src, &wallpaper_dst)?;
+        assert!(!dst.path().join(DEFAULT_PREFIX).exists());
+    }
 
-        info!("{}: generating preview ...", id);
-        generate_preview(
-            &src,
-            &dst.join(KDE_META_BASE)
-                .join(id)
-                .join("contents/screenshot.jpg"),
-        )?;
+    #[test]
+    fn test_config() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let home = tempfile::tempdir().unwrap();
+        fs::write(
+            src.path().join("wpmeta.toml"),
+            "preview_resolution_limit = \"100x100\"\ntargets = [\"kde\", \"gnome\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            home.path().join("wpmeta.toml"),
+            "preview_resolution_limit = \"50x50\"\nblurhash = true\nderef_sources = false\n",
+        )
+        .unwrap();
+        let parse = |extra: &[&str]| {
+            let mut argv = vec![
+                "wpmeta",
+                "--src",
+                src.path().to_str().unwrap(),
+                "--dst",
+                "out",
+            ];
+            argv.extend_from_slice(extra);
+            let argv = argv.into_iter().map(Into::into).collect();
+            parse_cli(argv, Some(home.path()))
+                .unwrap()
+                .args
+                .unwrap()
+                .options
+        };
+        let options = parse(&[]);
+        assert_eq!(
+            options.preview_resolution_limit,
+            Resolution::new(100, 100).unwrap()
+        );
+        assert_eq!(options.targets, [Target::Kde, Target::Gnome]);
+        assert!(options.blurhash);
+        assert!(!options.deref_sources);
+        let options = parse(&["--preview-resolution-limit", "200x200", "--targets", "sway"]);
+        assert_eq!(
+            options.preview_resolution_limit,
+            Resolution::new(200, 200).unwrap()
+        );
+        assert_eq!(options.targets, [Target::Sway]);
+
+        fs::write(src.path().join("wpmeta.toml"), "src = \"elsewhere\"\n").unwrap();
+        let argv = [
+            "wpmeta",
+            "--src",
+            src.path().to_str().unwrap(),
+            "--dst",
+            "out",
+        ];
+        assert!(parse_cli(argv.into_iter().map(Into::into).collect(), None).is_err());
     }
-    Ok(())
-}
 
-fn main() -> Result<()> {
-    pretty_env_logger::init_custom_env("WPMETA_LOG");
-    let args = Args::parse();
-    let metas = walk::walk(&args.src, None)?;
-
-    debug!("processing: {:?}", metas);
-    let _: Vec<()> = metas
-        .into_par_iter()
-        .map(|m| {
-            process_meta(m, &args.dst)
-                .wrap_err("failed to process wallpapers")
-                .unwrap();
-        })
-        .collect();
-    Ok(())
+    #[test]
+    fn test_install_path() {
+        let meta = format!("{}install_path = \"backgrounds/kusa\"\n", DUMMY_TREE_META);
+        let src = dummy_tree(&meta);
+        let dst = tempfile::tempdir().unwrap();
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &["--targets", "gnome,kde"],
+        ))
+        .unwrap();
+        let prefix = dst.path().join(DEFAULT_PREFIX);
+        let package = prefix.join("backgrounds/kusa");
+        assert!(package.join("contents/images/64x36.png").is_file());
+        assert!(package.join("contents/screenshot.jpg").is_file());
+        assert!(package.join("metadata.json").is_file());
+        assert!(!prefix.join(KDE_META_BASE).exists());
+        let xml = fs::read_to_string(prefix.join(GNOME_META_BASE).join("Kusa.xml")).unwrap();
+        assert!(xml.contains(
+            "<filename>/usr/share/backgrounds/kusa/contents/images/64x36.png</filename>"
+        ));
+    }
+
+    #[test]
+    fn test_install_root() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let args = parse_args(
+            src.path(),
+            dst.path(),
+            &[
+                "--install-root",
+                "/opt/wallpapers",
+                "--targets",
+                "gnome,mate,sway",
+            ],
+        );
+        run(&args).unwrap();
+        // The staging layout doesn't change
+        let prefix = dst.path().join(DEFAULT_PREFIX);
+        let xml = fs::read_to_string(prefix.join(GNOME_META_BASE).join("Kusa.xml")).unwrap();
+        assert!(xml.contains(
+            "<filename>/opt/wallpapers/usr/share/wallpapers/Kusa/contents/images/64x36.png</filename>"
+        ));
+        let sway = fs::read_to_string(prefix.join("wpmeta/sway/Kusa.conf")).unwrap();
+        assert!(sway.contains("bg /opt/wallpapers/usr/share/wallpapers/Kusa/"));
+        let mate = fs::read_link(prefix.join(MATE_META_BASE).join("Kusa.xml")).unwrap();
+        assert_eq!(
+            mate,
+            Path::new("/opt/wallpapers/usr/share/gnome-background-properties/Kusa.xml")
+        );
+        let args = parse_args(src.path(), dst.path(), &["--install-root", "opt"]);
+        assert!(run(&args).is_err());
+    }
+
+    #[test]
+    fn test_progress() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &["--progress", "--targets", "gnome"],
+        ))
+        .unwrap();
+        let xml = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(GNOME_META_BASE)
+            .join("Kusa.xml");
+        assert!(xml.is_file());
+    }
+
+    #[test]
+    fn test_resolutions() {
+        let meta = DUMMY_TREE_META.replace(
+            r#"path = "kusa.png""#,
+            "path = \"kusa.png\"\nresolutions = [\"kusa-large.png\"]",
+        );
+        let src = dummy_tree(&meta);
+        RgbImage::from_pixel(128, 72, image::Rgb([2, 60, 136]))
+            .save(src.path().join("kusa-large.png"))
+            .unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let report = dst.path().join("report.json");
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &[
+                "--targets",
+                "kde",
+                "--preview-resolution-limit",
+                "100x50",
+                "--report",
+                report.to_str().unwrap(),
+            ],
+        ))
+        .unwrap();
+        let images = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa/contents/images");
+        assert!(images.join("64x36.png").is_file());
+        assert!(images.join("128x72.png").is_file());
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(report).unwrap()).unwrap();
+        assert_eq!(report["Kusa"]["images"].as_array().unwrap().len(), 2);
+        // The preview is scaled down from the larger image
+        let preview = images.with_file_name("screenshot.jpg");
+        assert_eq!(image::image_dimensions(preview).unwrap(), (89, 50));
+    }
+
+    #[test]
+    fn test_extra_previews() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let report = dst.path().join("report.json");
+        run(&parse_args(
+            src.path(),
+            dst.path(),
+            &[
+                "--targets",
+                "kde",
+                "--preview-resolution-limit",
+                "32x32",
+                "--extra-preview",
+                "thumbnail=16x16",
+                "--report",
+                report.to_str().unwrap(),
+            ],
+        ))
+        .unwrap();
+        let contents = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa/contents");
+        let dimensions = |name: &str| image::image_dimensions(contents.join(name)).unwrap();
+        assert_eq!(dimensions("screenshot.jpg"), (32, 18));
+        assert_eq!(dimensions("thumbnail.jpg"), (16, 9));
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(report).unwrap()).unwrap();
+        assert_eq!(
+            report["Kusa"]["extra_previews"][0],
+            "usr/share/wallpapers/Kusa/contents/thumbnail.jpg"
+        );
+    }
+
+    #[test]
+    fn test_overwrite_policy() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let generate = |policy: &str| {
+            let dst = tempfile::tempdir().unwrap();
+            let gnome = dst
+                .path()
+                .join(DEFAULT_PREFIX)
+                .join(GNOME_META_BASE)
+                .join("Kusa.xml");
+            fs::create_dir_all(gnome.parent().unwrap()).unwrap();
+            fs::write(&gnome, "mine").unwrap();
+            let args = parse_args(
+                src.path(),
+                dst.path(),
+                &["--targets", "gnome", "--overwrite-policy", policy],
+            );
+            let result = run(&args);
+            (result, fs::read_to_string(&gnome).unwrap(), dst)
+        };
+
+        let (result, content, _) = generate("overwrite");
+        result.unwrap();
+        assert!(content.contains("<wallpapers>"));
+
+        let (result, content, dst) = generate("skip");
+        result.unwrap();
+        assert_eq!(content, "mine");
+        // Files not there yet are still written
+        assert!(dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join("wallpapers/Kusa/contents/images/64x36.png")
+            .is_file());
+
+        let (result, content, _) = generate("error");
+        assert!(format!("{:?}", result.unwrap_err()).contains("Kusa.xml already exists"));
+        assert_eq!(content, "mine");
+    }
+
+    #[test]
+    fn test_unchanged_manifests() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let manifest = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa/metadata.json");
+        let mtime = || fs::metadata(&manifest).unwrap().modified().unwrap();
+        let generate = |extra: &[&str]| {
+            let mut args = vec!["--targets", "kde"];
+            args.extend_from_slice(extra);
+            run(&parse_args(src.path(), dst.path(), &args)).unwrap();
+        };
+        generate(&[]);
+        // Backdated so any rewrite shows, however coarse the clock
+        let past = std::time::UNIX_EPOCH + std::time::Duration::from_secs(86400);
+        fs::File::options()
+            .write(true)
+            .open(&manifest)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        generate(&[]);
+        assert_eq!(mtime(), past);
+        generate(&["--force"]);
+        assert_ne!(mtime(), past);
+    }
+
+    #[test]
+    fn test_preview_source() {
+        let meta = DUMMY_TREE_META.replace(
+            r#"path = "kusa.png""#,
+            r#"path = ["kusa.png", "kusa-dark.png"]"#,
+        );
+        let src = dummy_tree(&meta);
+        let preview = |source: &str| {
+            let dst = tempfile::tempdir().unwrap();
+            let args = parse_args(
+                src.path(),
+                dst.path(),
+                &["--targets", "kde", "--preview-source", source],
+            );
+            run(&args).unwrap();
+            let path = dst
+                .path()
+                .join(DEFAULT_PREFIX)
+                .join(KDE_META_BASE)
+                .join("Kusa/contents/screenshot.jpg");
+            // The dummy images are solid blue and black
+            path.is_file()
+                .then(|| image::open(&path).unwrap().to_rgb8().get_pixel(0, 0).0)
+        };
+        let normal = preview("normal").unwrap();
+        assert!(normal[2] > 100, "{:?} should be blue", normal);
+        let dark = preview("dark").unwrap();
+        assert!(dark.iter().all(|c| *c < 20), "{:?} should be black", dark);
+        assert_eq!(preview("auto"), None);
+
+        // Without a dark variant every mode previews the normal image
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let args = parse_args(
+            src.path(),
+            dst.path(),
+            &["--targets", "kde", "--preview-source", "auto"],
+        );
+        run(&args).unwrap();
+        assert!(dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(KDE_META_BASE)
+            .join("Kusa/contents/screenshot.jpg")
+            .is_file());
+    }
 }
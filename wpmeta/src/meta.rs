@@ -1,23 +1,39 @@
-use eyre::{eyre, Result};
+use eyre::{bail, Result};
 use hex_color::HexColor;
 use image::io::Reader;
-use serde::{Deserialize, Serialize};
+use image::DynamicImage;
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use spdx::Expression;
 
-use locale::Localized;
+use locale::{Locale, Localized};
 
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+use crate::color;
+use crate::error::WpmetaError;
+use crate::orientation;
+use crate::palette::{self, PaletteConfig};
+use crate::paths::{self, KDE_META_BASE};
+use crate::po::Catalog;
+use crate::preview;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Author {
     email: String,
     name: Localized<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum PictureOptions {
     None,
+    #[default]
     Wallpaper,
     Centered,
     Scaled,
@@ -26,50 +42,251 @@ pub enum PictureOptions {
     Spanned,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// What a wallpaper shows, for stores to group wallpapers by.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Abstract,
+    Animals,
+    Architecture,
+    City,
+    Illustration,
+    Minimal,
+    Nature,
+    Space,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ColorShadingType {
     Horizontal,
     Vertical,
+    #[default]
     Solid,
 }
 
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WallpaperKind {
+    #[default]
+    Normal,
+    Dark,
+}
+
+/// A source file with an optional explicit variant. Plain paths are dark
+/// variants if their file name ends with `dark`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+enum WallpaperPathEntry {
+    Plain(PathBuf),
+    Explicit {
+        file: PathBuf,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        variant: Option<WallpaperKind>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+enum WallpaperPath {
+    Single(PathBuf),
+    Multiple(Vec<WallpaperPathEntry>),
+}
+
+/// Source files of a wallpaper, resolved by variant.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "WallpaperPath", into = "WallpaperPath")]
+pub struct WallpaperPaths {
+    normal: PathBuf,
+    dark: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Width to height ratio of an image in lowest terms, e.g. `16:9`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AspectRatio {
+    width: u32,
+    height: u32,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct WallpaperFileMeta {
     target: PathBuf,
     dimensions: (u32, u32),
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct WallpaperFile {
+    #[serde(deserialize_with = "deserialize_id")]
     id: String,
-    path: PathBuf,
+    #[serde(rename = "path")]
+    paths: WallpaperPaths,
+    /// Package directory under the install prefix, `wallpapers/<id>` if unset
+    #[serde(
+        default,
+        deserialize_with = "deserialize_install_path",
+        skip_serializing_if = "Option::is_none"
+    )]
+    install_path: Option<PathBuf>,
     #[serde(skip)]
     meta: OnceLock<WallpaperFileMeta>,
+    #[serde(skip)]
+    dark_meta: OnceLock<WallpaperFileMeta>,
+    #[serde(skip)]
+    image: ImageCache,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+/// The decoded image of a source, shared by everything working on pixels
+/// until released. It is not part of the file's identity.
+#[derive(Debug, Default)]
+struct ImageCache(Mutex<Option<Arc<DynamicImage>>>);
+
+impl Clone for ImageCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for ImageCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ImageCache {}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Wallpaper {
     title: Localized<String>,
-    license: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<Localized<String>>,
+    /// Taken from the `[defaults]` of the metadata if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license: Option<String>,
+    /// Credit the license asks for, e.g. by CC BY
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attribution: Option<Localized<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license_url: Option<String>,
     #[serde(flatten)]
     file: WallpaperFile,
-    #[serde(default)]
-    option: PictureOptions,
-    #[serde(default)]
-    shade_type: ColorShadingType,
-    #[serde(default = "default_primary_color")]
-    primary_color: HexColor,
-    #[serde(default = "default_secondary_color")]
-    secondary_color: HexColor,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    option: Option<PictureOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shade_type: Option<ColorShadingType>,
+    #[serde(
+        default,
+        deserialize_with = "color::deserialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    primary_color: Option<HexColor>,
+    #[serde(
+        default,
+        deserialize_with = "color::deserialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    secondary_color: Option<HexColor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<Category>,
+    #[serde(skip)]
+    blurhash: Option<String>,
+    /// Expected aspect ratio of the image, checked against the actual one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<AspectRatio>,
+    /// Emails of the authors credited for this wallpaper, all authors if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    authors: Option<Vec<String>>,
+    /// The same image at other resolutions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    resolutions: Vec<PathBuf>,
+    /// Switch between the normal and dark variants by time of day on GNOME
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    timed: bool,
+    #[serde(skip)]
+    resolution_files: OnceLock<Vec<WallpaperFile>>,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Collection {
+    #[serde(deserialize_with = "deserialize_id")]
+    id: String,
+    name: Localized<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<Localized<String>>,
+}
+
+/// Values the wallpapers of a metadata file and of those below it inherit
+/// unless they set them.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Defaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    option: Option<PictureOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shade_type: Option<ColorShadingType>,
+    #[serde(
+        default,
+        deserialize_with = "color::deserialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    primary_color: Option<HexColor>,
+    #[serde(
+        default,
+        deserialize_with = "color::deserialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    secondary_color: Option<HexColor>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Metadata {
     #[serde(skip)]
     base: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    defaults: Option<Defaults>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     authors: Option<Vec<Author>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     wallpapers: Option<Vec<Wallpaper>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collection: Option<Collection>,
+}
+
+/// Adds the translation of the default value to `target`, keeping
+/// translations already present in the manifest.
+fn translate(target: &mut Localized<String>, locale: &Locale, catalog: &Catalog) {
+    if target.get(locale).is_some() {
+        return;
+    }
+    if let Some(msgstr) = target.get_default().and_then(|d| catalog.get(d)) {
+        target.set(locale.clone(), msgstr.to_owned());
+    }
+}
+
+/// Merges authors sharing an email, later entries taking precedence.
+fn merge_authors(authors: Vec<Author>) -> Vec<Author> {
+    let mut ret: Vec<Author> = Vec::with_capacity(authors.len());
+    for author in authors {
+        match ret.iter_mut().find(|a| a.email == author.email) {
+            Some(existing) => {
+                existing.name.merge(author.name);
+                if author.role.is_some() {
+                    existing.role = author.role;
+                }
+            }
+            None => ret.push(author),
+        }
+    }
+    ret
 }
 
 #[inline]
@@ -80,13 +297,9 @@ where
     inner.map(|t| t.to_owned())
 }
 
-fn default_primary_color() -> HexColor {
-    HexColor::rgb(2, 60, 136)
-}
-
-fn default_secondary_color() -> HexColor {
-    HexColor::rgb(87, 137, 202)
-}
+static DEFAULT_PRIMARY_COLOR: HexColor = HexColor::rgb(2, 60, 136);
+static DEFAULT_SECONDARY_COLOR: HexColor = HexColor::rgb(87, 137, 202);
+static MIN_CONTRAST_RATIO: f64 = 3.0;
 
 impl Author {
     pub fn email(&self) -> &str {
@@ -96,39 +309,428 @@ impl Author {
     pub fn name(&self) -> &Localized<String> {
         &self.name
     }
+
+    pub fn role(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+}
+
+impl Collection {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &Localized<String> {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&Localized<String>> {
+        self.description.as_ref()
+    }
+}
+
+impl PictureOptions {
+    /// Maps the option to its `options` value in GNOME wallpaper lists.
+    pub fn to_gnome_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Wallpaper => "wallpaper",
+            Self::Centered => "centered",
+            Self::Scaled => "scaled",
+            Self::Stretched => "stretched",
+            Self::Zoom => "zoom",
+            Self::Spanned => "spanned",
+        }
+    }
+
+    /// Maps the option to the closest `swaybg` scaling mode.
+    pub fn to_swaybg_mode(self) -> &'static str {
+        match self {
+            Self::None => "solid_color",
+            Self::Wallpaper => "tile",
+            Self::Centered => "center",
+            Self::Scaled => "fit",
+            Self::Stretched => "stretch",
+            // swaybg has no spanning mode, fill is the closest match
+            Self::Zoom | Self::Spanned => "fill",
+        }
+    }
 }
 
-impl Default for PictureOptions {
-    fn default() -> Self {
-        Self::Wallpaper
+impl Category {
+    /// Maps the category to its `Category` value in KDE metadata.
+    pub fn to_kde_str(self) -> &'static str {
+        match self {
+            Self::Abstract => "Abstract",
+            Self::Animals => "Animals",
+            Self::Architecture => "Architecture",
+            Self::City => "City",
+            Self::Illustration => "Illustration",
+            Self::Minimal => "Minimal",
+            Self::Nature => "Nature",
+            Self::Space => "Space",
+        }
     }
 }
 
-impl Default for ColorShadingType {
-    fn default() -> Self {
-        Self::Solid
+impl ColorShadingType {
+    /// Maps the type to its `shade_type` value in GNOME wallpaper lists.
+    pub fn to_gnome_str(self) -> &'static str {
+        match self {
+            Self::Horizontal => "horizontal",
+            Self::Vertical => "vertical",
+            Self::Solid => "solid",
+        }
     }
 }
 
+impl Resolution {
+    /// Creates a resolution, rejecting zero dimensions.
+    pub fn new(width: u32, height: u32) -> Result<Self, WpmetaError> {
+        if width == 0 || height == 0 {
+            return Err(WpmetaError::InvalidResolution {
+                input: format!("{}x{}", width, height),
+                reason: "dimensions must be positive".into(),
+            });
+        }
+        Ok(Self { width, height })
+    }
+
+    /// Scales the resolution down, keeping the aspect ratio, until it fits
+    /// within `limit`. Resolutions already within `limit` are unchanged.
+    pub fn fit_within(&self, limit: Resolution) -> Resolution {
+        if self.width <= limit.width && self.height <= limit.height {
+            return *self;
+        }
+        let (width, height) = (u64::from(self.width), u64::from(self.height));
+        let (limit_width, limit_height) = (u64::from(limit.width), u64::from(limit.height));
+        // Compare width / height against limit_width / limit_height without floats
+        let (width, height) = if width * limit_height >= height * limit_width {
+            (limit_width, (height * limit_width + width / 2) / width)
+        } else {
+            ((width * limit_height + height / 2) / height, limit_height)
+        };
+        Resolution {
+            width: width.max(1) as u32,
+            height: height.max(1) as u32,
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = WpmetaError;
+
+    /// Parses `WIDTHxHEIGHT` or `WIDTH,HEIGHT`.
+    fn from_str(s: &str) -> Result<Self, WpmetaError> {
+        let invalid = |reason: String| WpmetaError::InvalidResolution {
+            input: s.to_owned(),
+            reason,
+        };
+        let (width, height) = s
+            .split_once([',', 'x', 'X'])
+            .ok_or_else(|| invalid("expected WIDTHxHEIGHT".into()))?;
+        let parse = |dimension: &str| -> Result<u32, WpmetaError> {
+            let dimension = dimension.trim();
+            match dimension.parse::<i64>() {
+                Ok(d) if d <= 0 => Err(invalid("dimensions must be positive".into())),
+                Ok(d) => u32::try_from(d).map_err(|_| invalid(format!("{} is too large", d))),
+                Err(e) => Err(invalid(format!("{:?}: {}", dimension, e))),
+            }
+        };
+        Ok(Self {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+impl TryFrom<&str> for Resolution {
+    type Error = WpmetaError;
+
+    fn try_from(value: &str) -> Result<Self, WpmetaError> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for Resolution {
+    type Error = WpmetaError;
+
+    fn try_from(value: String) -> Result<Self, WpmetaError> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl Defaults {
+    /// Fills in what is unset from `parent`.
+    fn inherit(&mut self, parent: &Defaults) {
+        self.license = self.license.take().or_else(|| parent.license.clone());
+        self.option = self.option.or(parent.option);
+        self.shade_type = self.shade_type.or(parent.shade_type);
+        self.primary_color = self.primary_color.or(parent.primary_color);
+        self.secondary_color = self.secondary_color.or(parent.secondary_color);
+    }
+
+    /// Fills in what `wallpaper` leaves unset.
+    fn apply(&self, wallpaper: &mut Wallpaper) {
+        if wallpaper.license.is_none() {
+            wallpaper.license = self.license.clone();
+        }
+        wallpaper.option = wallpaper.option.or(self.option);
+        wallpaper.shade_type = wallpaper.shade_type.or(self.shade_type);
+        wallpaper.primary_color = wallpaper.primary_color.or(self.primary_color);
+        wallpaper.secondary_color = wallpaper.secondary_color.or(self.secondary_color);
+    }
+}
+
+impl AspectRatio {
+    /// Reduces `width`:`height` to lowest terms.
+    pub fn new(width: u32, height: u32) -> Result<Self, WpmetaError> {
+        if width == 0 || height == 0 {
+            return Err(WpmetaError::InvalidAspectRatio {
+                input: format!("{}:{}", width, height),
+                reason: "sides must be positive".into(),
+            });
+        }
+        let gcd = {
+            let (mut a, mut b) = (width, height);
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            a
+        };
+        Ok(Self {
+            width: width / gcd,
+            height: height / gcd,
+        })
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = WpmetaError;
+
+    /// Parses `WIDTH:HEIGHT`, reducing it to lowest terms.
+    fn from_str(s: &str) -> Result<Self, WpmetaError> {
+        let invalid = |reason: &str| WpmetaError::InvalidAspectRatio {
+            input: s.to_owned(),
+            reason: reason.to_owned(),
+        };
+        let (width, height) = s
+            .split_once(':')
+            .ok_or_else(|| invalid("expected WIDTH:HEIGHT"))?;
+        let parse = |side: &str| {
+            side.trim()
+                .parse::<u32>()
+                .map_err(|_| invalid("sides must be positive integers"))
+        };
+        Self::new(parse(width)?, parse(height)?).map_err(|_| invalid("sides must be positive"))
+    }
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = WpmetaError;
+
+    fn try_from(value: String) -> Result<Self, WpmetaError> {
+        value.parse()
+    }
+}
+
+impl From<AspectRatio> for String {
+    fn from(value: AspectRatio) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+impl WallpaperKind {
+    /// Infers the variant from the file name, e.g. `kusa-dark.jpg`. Only the
+    /// last extension is stripped, so `kusa.v2.dark.jpg` is dark as well.
+    pub fn from_file_name(path: &Path) -> Self {
+        match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) if stem.to_lowercase().ends_with("dark") => Self::Dark,
+            _ => Self::Normal,
+        }
+    }
+
+    /// Directory of the KDE package holding images of this variant.
+    fn images_dir(&self) -> &'static str {
+        match self {
+            Self::Normal => "images",
+            Self::Dark => "images_dark",
+        }
+    }
+}
+
+impl WallpaperPathEntry {
+    fn resolve(self) -> (WallpaperKind, PathBuf) {
+        match self {
+            Self::Plain(file)
+            | Self::Explicit {
+                file,
+                variant: None,
+            } => (WallpaperKind::from_file_name(&file), file),
+            Self::Explicit {
+                file,
+                variant: Some(variant),
+            } => (variant, file),
+        }
+    }
+}
+
+impl From<WallpaperPaths> for WallpaperPath {
+    fn from(value: WallpaperPaths) -> Self {
+        let Some(dark) = value.dark else {
+            return Self::Single(value.normal);
+        };
+        // Spell out variants the file names don't imply
+        let entry = |file: PathBuf, kind| match WallpaperKind::from_file_name(&file) == kind {
+            true => WallpaperPathEntry::Plain(file),
+            false => WallpaperPathEntry::Explicit {
+                file,
+                variant: Some(kind),
+            },
+        };
+        Self::Multiple(vec![
+            entry(value.normal, WallpaperKind::Normal),
+            entry(dark, WallpaperKind::Dark),
+        ])
+    }
+}
+
+impl TryFrom<WallpaperPath> for WallpaperPaths {
+    type Error = eyre::Report;
+
+    fn try_from(value: WallpaperPath) -> Result<Self> {
+        let entries = match value {
+            // A lone path is always the normal variant
+            WallpaperPath::Single(normal) => return Ok(Self { normal, dark: None }),
+            WallpaperPath::Multiple(entries) => entries,
+        };
+        let (mut normal, mut dark) = (None, None);
+        for entry in entries {
+            let (kind, file) = entry.resolve();
+            let slot = match kind {
+                WallpaperKind::Normal => &mut normal,
+                WallpaperKind::Dark => &mut dark,
+            };
+            if let Some(existing) = slot.replace(file) {
+                bail!(
+                    "more than one {:?} variant, {} is also given",
+                    kind,
+                    existing.display()
+                );
+            }
+        }
+        // Desktops need a normal image, a lone dark variant is used as one
+        let (normal, dark) = match (normal, dark) {
+            (Some(normal), dark) => (normal, dark),
+            (None, Some(dark)) => {
+                warn!(
+                    "only a dark variant {} given, using it as the normal one",
+                    dark.display()
+                );
+                (dark, None)
+            }
+            (None, None) => bail!("no wallpaper file given"),
+        };
+        Ok(Self { normal, dark })
+    }
+}
+
+/// Deserializes an install path, which has to stay inside the prefix.
+fn deserialize_install_path<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path = PathBuf::deserialize(deserializer)?;
+    let inside = path.components().next().is_some() && paths::is_contained(&path);
+    if !inside {
+        return Err(serde::de::Error::custom(format!(
+            "install path {} must be relative and inside the prefix",
+            path.display()
+        )));
+    }
+    Ok(Some(path))
+}
+
+/// Checks that `id` can name a directory of its own, as packages are
+/// installed under `wallpapers/<id>`.
+fn check_id(id: &str) -> Result<(), WpmetaError> {
+    let reason = if id.trim().is_empty() {
+        "must not be empty"
+    } else if id.contains(['/', '\\']) {
+        "must not contain path separators"
+    } else if id == "." || id == ".." {
+        "must not be a relative directory"
+    } else if id.chars().any(char::is_control) {
+        "must not contain control characters"
+    } else {
+        return Ok(());
+    };
+    Err(WpmetaError::InvalidId {
+        id: id.to_owned(),
+        reason,
+    })
+}
+
+fn deserialize_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let id = String::deserialize(deserializer)?;
+    check_id(&id).map_err(serde::de::Error::custom)?;
+    Ok(id)
+}
+
 impl WallpaperFileMeta {
-    pub fn new(id: &str, file: &Path) -> Result<Self> {
-        let img = Reader::open(file)?.decode()?;
-        let (width, height) = (img.width(), img.height());
-        let extension = file
-            .extension()
-            .ok_or_else(|| eyre!("cannot get file extension"))?
-            .to_str()
-            .ok_or_else(|| eyre!("cannot parse file extension"))?;
-        // TODO: Implement automatic palette extraction
+    /// Reads the image at `file`, to be installed in the KDE package at
+    /// `package` under the install prefix.
+    pub fn new(package: &Path, file: &Path, kind: WallpaperKind) -> Result<Self, WpmetaError> {
+        let reader = Reader::open(file)
+            .and_then(|r| r.with_guessed_format())
+            .map_err(|e| WpmetaError::io(file, e))?;
+        let format = reader
+            .format()
+            .ok_or_else(|| WpmetaError::UnknownFormat(file.to_owned()))?;
+        // Name the copy after the detected format rather than the source extension
+        let extension = format.extensions_str()[0];
+        // Only the header is read, decoding is left to those needing pixels
+        let (width, height) =
+            reader
+                .into_dimensions()
+                .map_err(|source| WpmetaError::ImageDecode {
+                    path: file.to_owned(),
+                    source,
+                })?;
+        // Headers may declare an empty canvas, which no ratio can describe
+        if width == 0 || height == 0 {
+            return Err(WpmetaError::EmptyImage(file.to_owned()));
+        }
+        // Dimensions as displayed, the copy itself keeps its orientation
+        let (width, height) = match orientation::read(file) {
+            5..=8 => (height, width),
+            _ => (width, height),
+        };
         Ok(Self {
-            target: PathBuf::from(format!(
-                "usr/share/wallpapers/{}/contents/images/{}x{}.{}",
-                id, width, height, extension
-            )),
+            target: package
+                .join("contents")
+                .join(kind.images_dir())
+                .join(format!("{}x{}.{}", width, height, extension)),
             dimensions: (width, height),
         })
     }
 
+    /// Installed path of the image, relative to the install prefix.
     pub fn target(&self) -> &Path {
         &self.target
     }
@@ -136,24 +738,89 @@ impl WallpaperFileMeta {
     pub fn dimensions(&self) -> (u32, u32) {
         self.dimensions
     }
+
+    pub fn aspect_ratio(&self) -> AspectRatio {
+        let (width, height) = self.dimensions;
+        AspectRatio::new(width, height).expect("Image headers of zero size are rejected on reading")
+    }
 }
 
 impl WallpaperFile {
+    /// Creates a file for `normal`, kept as given. Nothing is read until the
+    /// metadata is requested, so the file may not exist yet.
+    pub fn new(id: &str, normal: PathBuf) -> Self {
+        Self {
+            id: id.to_owned(),
+            paths: WallpaperPaths { normal, dark: None },
+            install_path: None,
+            meta: OnceLock::new(),
+            dark_meta: OnceLock::new(),
+            image: ImageCache::default(),
+        }
+    }
+
     pub fn src(&self) -> &Path {
-        &self.path
-    }
-
-    pub fn get_meta(&self, base: &Path) -> &WallpaperFileMeta {
-        self.meta.get_or_init(|| {
-            let id = &self.id;
-            let path = &base.join(&self.path);
-            // TODO: Use get_or_try_init
-            WallpaperFileMeta::new(id, path).expect(&format!(
-                "{}: failed to process image metadata for image at {}",
-                id,
-                path.display()
-            ))
-        })
+        &self.paths.normal
+    }
+
+    /// Directory of the KDE package holding the images, relative to the
+    /// install prefix.
+    pub fn package_dir(&self) -> PathBuf {
+        match &self.install_path {
+            Some(path) => path.clone(),
+            None => Path::new(KDE_META_BASE).join(&self.id),
+        }
+    }
+
+    pub fn dark_src(&self) -> Option<&Path> {
+        self.paths.dark.as_deref()
+    }
+
+    /// Returns the metadata cached in `cell`, reading the image at `path`
+    /// if there is none yet.
+    fn load_meta<'a>(
+        &self,
+        cell: &'a OnceLock<WallpaperFileMeta>,
+        path: &Path,
+        kind: WallpaperKind,
+    ) -> Result<&'a WallpaperFileMeta, WpmetaError> {
+        if let Some(meta) = cell.get() {
+            return Ok(meta);
+        }
+        let meta = WallpaperFileMeta::new(&self.package_dir(), path, kind)?;
+        Ok(cell.get_or_init(|| meta))
+    }
+
+    /// Decodes the image in its displayed orientation, or returns the copy
+    /// decoded before if it was not released since.
+    pub fn image(&self, base: &Path) -> Result<Arc<DynamicImage>, WpmetaError> {
+        let mut cached = self.image.0.lock().expect("Image cache lock poisoned");
+        if let Some(img) = &*cached {
+            return Ok(img.clone());
+        }
+        let img = Arc::new(orientation::open(&base.join(self.src()))?);
+        *cached = Some(img.clone());
+        Ok(img)
+    }
+
+    /// Aspect ratio of the image, in its displayed orientation.
+    pub fn aspect_ratio(&self, base: &Path) -> Result<AspectRatio, WpmetaError> {
+        Ok(self.get_meta(base)?.aspect_ratio())
+    }
+
+    /// Drops the decoded image, if any.
+    pub fn release_image(&self) {
+        *self.image.0.lock().expect("Image cache lock poisoned") = None;
+    }
+
+    pub fn get_meta(&self, base: &Path) -> Result<&WallpaperFileMeta, WpmetaError> {
+        self.load_meta(&self.meta, &base.join(self.src()), WallpaperKind::Normal)
+    }
+
+    pub fn get_dark_meta(&self, base: &Path) -> Result<Option<&WallpaperFileMeta>, WpmetaError> {
+        self.dark_src()
+            .map(|dark| self.load_meta(&self.dark_meta, &base.join(dark), WallpaperKind::Dark))
+            .transpose()
     }
 
     pub fn id(&self) -> &str {
@@ -170,8 +837,71 @@ impl Wallpaper {
         &self.title
     }
 
+    pub fn description(&self) -> Option<&Localized<String>> {
+        self.description.as_ref()
+    }
+
+    /// The license, empty if neither the wallpaper nor its defaults give
+    /// one. Walked metadata always has one.
     pub fn license(&self) -> &str {
-        &self.license
+        self.license.as_deref().unwrap_or_default()
+    }
+
+    pub fn attribution(&self) -> Option<&Localized<String>> {
+        self.attribution.as_ref()
+    }
+
+    pub fn license_url(&self) -> Option<&str> {
+        self.license_url.as_deref()
+    }
+
+    /// Renders the `LICENSE.txt` installed with the wallpaper, if it has an
+    /// attribution or license URL to tell. Only the default attribution is
+    /// written. Licenses that are not valid SPDX expressions, which only get
+    /// this far without `--strict-license`, are written as given.
+    pub fn license_text(&self) -> Option<String> {
+        let attribution = self.attribution.as_ref().and_then(|a| a.best_default());
+        if attribution.is_none() && self.license_url.is_none() {
+            return None;
+        }
+        let license = self
+            .canonical_license()
+            .unwrap_or_else(|_| self.license().to_owned());
+        let mut ret = format!("SPDX-License-Identifier: {}\n", license);
+        if let Some(url) = &self.license_url {
+            ret.push_str(&format!("License-URL: {}\n", url));
+        }
+        if let Some(attribution) = attribution {
+            ret.push_str(&format!("\n{}\n", attribution.trim_end()));
+        }
+        Some(ret)
+    }
+
+    /// Returns the license as a canonical SPDX expression.
+    pub fn canonical_license(&self) -> Result<String> {
+        let canonical = Expression::canonicalize(self.license())?;
+        Ok(canonical.unwrap_or_else(|| self.license().to_owned()))
+    }
+
+    /// Warns about licenses that are not valid SPDX expressions, or fails
+    /// on them if `strict` is set.
+    pub fn check_license(&self, strict: bool) -> Result<()> {
+        if let Err(e) = self.canonical_license() {
+            if strict {
+                bail!(
+                    "{}: invalid SPDX license expression {:?}: {}",
+                    self.id(),
+                    self.license(),
+                    e
+                );
+            }
+            warn!(
+                "{}: invalid SPDX license expression {:?}",
+                self.id(),
+                self.license()
+            );
+        }
+        Ok(())
     }
 
     pub fn file(&self) -> &WallpaperFile {
@@ -182,20 +912,218 @@ impl Wallpaper {
         self.file().src()
     }
 
-    pub fn target(&self, base: &Path) -> &Path {
-        self.file().get_meta(base).target()
+    /// Directory of the KDE package of the wallpaper, relative to the
+    /// install prefix.
+    pub fn package_dir(&self) -> PathBuf {
+        self.file().package_dir()
+    }
+
+    /// Fills in the colors the wallpaper doesn't specify from its image.
+    pub fn extract_colors(&mut self, base: &Path, config: &PaletteConfig) -> Result<()> {
+        if self.primary_color.is_some() && self.secondary_color.is_some() {
+            return Ok(());
+        }
+        let img = self.file.image(base)?;
+        let (primary, accent) = palette::extract_colors_with(&img, config)?;
+        self.primary_color.get_or_insert(primary);
+        self.secondary_color.get_or_insert(accent);
+        Ok(())
+    }
+
+    /// Computes the BlurHash placeholder of the image.
+    pub fn compute_blurhash(&mut self, base: &Path) -> Result<()> {
+        let img = self.file.image(base)?;
+        self.blurhash = Some(preview::blurhash(&img)?);
+        Ok(())
+    }
+
+    /// Tags the wallpaper with the aspect ratio of its image, warning if it
+    /// differs from the expected one.
+    pub fn tag_aspect_ratio(&mut self, base: &Path) -> Result<(), WpmetaError> {
+        let actual = self.file.aspect_ratio(base)?;
+        if let Some(expected) = self.aspect_ratio.filter(|e| *e != actual) {
+            warn!(
+                "{}: image has an aspect ratio of {}, expected {}",
+                self.id(),
+                actual,
+                expected
+            );
+        }
+        self.aspect_ratio = Some(actual);
+        Ok(())
+    }
+
+    pub fn aspect_ratio(&self) -> Option<AspectRatio> {
+        self.aspect_ratio
+    }
+
+    /// Drops the decoded images of every source.
+    pub fn release_images(&self) {
+        for file in self.sources() {
+            file.release_image();
+        }
+    }
+
+    /// Installed path of the image under `prefix`.
+    pub fn target(&self, base: &Path, prefix: &Path) -> Result<PathBuf, WpmetaError> {
+        Ok(prefix.join(self.file().get_meta(base)?.target()))
     }
 
-    pub fn option(&self) -> &PictureOptions {
-        &self.option
+    pub fn dark_src(&self) -> Option<&Path> {
+        self.file().dark_src()
     }
 
-    pub fn shade_type(&self) -> &ColorShadingType {
-        &self.shade_type
+    /// Sources of the other resolutions of the image.
+    pub fn resolution_srcs(&self) -> impl Iterator<Item = &Path> {
+        self.resolutions.iter().map(PathBuf::as_path)
     }
 
-    pub fn colors(&self) -> (&HexColor, &HexColor) {
-        (&self.primary_color, &self.secondary_color)
+    /// Source files of the image at every resolution, the main one first.
+    pub fn sources(&self) -> impl Iterator<Item = &WallpaperFile> {
+        let others = self.resolution_files.get_or_init(|| {
+            self.resolutions
+                .iter()
+                .map(|path| WallpaperFile {
+                    install_path: self.file.install_path.clone(),
+                    ..WallpaperFile::new(self.id(), path.clone())
+                })
+                .collect()
+        });
+        std::iter::once(&self.file).chain(others)
+    }
+
+    /// Picks the smallest source covering `resolution`, or the largest one
+    /// if none does, so downscaling to `resolution` loses the least.
+    pub fn best_source_for(
+        &self,
+        base: &Path,
+        resolution: Resolution,
+    ) -> Result<&WallpaperFile, WpmetaError> {
+        let sources = self
+            .sources()
+            .map(|file| Ok((file, file.get_meta(base)?.dimensions())))
+            .collect::<Result<Vec<_>, WpmetaError>>()?;
+        let area = |(_, (width, height)): &&(_, (u32, u32))| u64::from(*width) * u64::from(*height);
+        let best = sources
+            .iter()
+            .filter(|(_, (width, height))| {
+                *width >= resolution.width && *height >= resolution.height
+            })
+            .min_by_key(area)
+            .or_else(|| sources.iter().max_by_key(area));
+        Ok(best.map_or(&self.file, |(file, _)| file))
+    }
+
+    pub fn dark_target(&self, base: &Path, prefix: &Path) -> Result<Option<PathBuf>, WpmetaError> {
+        let meta = self.file().get_dark_meta(base)?;
+        Ok(meta.map(|m| prefix.join(m.target())))
+    }
+
+    pub fn option(&self) -> PictureOptions {
+        self.option.unwrap_or_default()
+    }
+
+    pub fn shade_type(&self) -> ColorShadingType {
+        self.shade_type.unwrap_or_default()
+    }
+
+    pub fn colors(&self) -> (HexColor, HexColor) {
+        (
+            self.primary_color.unwrap_or(DEFAULT_PRIMARY_COLOR),
+            self.secondary_color.unwrap_or(DEFAULT_SECONDARY_COLOR),
+        )
+    }
+
+    /// Warns if the dark variant is not darker on average than the normal
+    /// image, which hints at mislabeled files, or fails if `enforce` is set.
+    pub fn check_dark_variant(&self, base: &Path, enforce: bool) -> Result<()> {
+        let Some(dark) = self.dark_src() else {
+            return Ok(());
+        };
+        let normal = palette::mean_luminance(&*self.file.image(base)?);
+        let dark_luminance = palette::mean_luminance(&orientation::open(&base.join(dark))?);
+        if dark_luminance < normal {
+            return Ok(());
+        }
+        if enforce {
+            bail!(
+                "{}: dark variant {} is not darker than the normal image ({:.2} vs {:.2} luminance)",
+                self.id(),
+                dark.display(),
+                dark_luminance,
+                normal
+            );
+        }
+        warn!(
+            "{}: dark variant {} is not darker than the normal image ({:.2} vs {:.2} luminance)",
+            self.id(),
+            dark.display(),
+            dark_luminance,
+            normal
+        );
+        Ok(())
+    }
+
+    /// Warns if the primary and secondary colors are hard to tell apart,
+    /// or fails if `enforce` is set. The built-in colors are not checked.
+    pub fn check_contrast(&self, enforce: bool) -> Result<()> {
+        if self.primary_color.is_none() && self.secondary_color.is_none() {
+            return Ok(());
+        }
+        let (primary, secondary) = self.colors();
+        let ratio = palette::contrast_ratio(primary, secondary);
+        if ratio >= MIN_CONTRAST_RATIO {
+            return Ok(());
+        }
+        if enforce {
+            bail!(
+                "{}: contrast ratio between {} and {} is {:.2}, below {}",
+                self.id(),
+                primary,
+                secondary,
+                ratio,
+                MIN_CONTRAST_RATIO
+            );
+        }
+        warn!(
+            "{}: low contrast ratio {:.2} between {} and {}",
+            self.id(),
+            ratio,
+            primary,
+            secondary
+        );
+        Ok(())
+    }
+
+    /// Fails if the image is smaller than `min` in either dimension.
+    pub fn check_resolution(&self, base: &Path, min: Resolution) -> Result<(), WpmetaError> {
+        let (width, height) = self.file().get_meta(base)?.dimensions();
+        if width < min.width || height < min.height {
+            return Err(WpmetaError::ResolutionTooLow {
+                id: self.id().to_owned(),
+                width,
+                height,
+                min_width: min.width,
+                min_height: min.height,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn category(&self) -> Option<Category> {
+        self.category
+    }
+
+    pub fn blurhash(&self) -> Option<&str> {
+        self.blurhash.as_deref()
+    }
+
+    pub fn timed(&self) -> bool {
+        self.timed
     }
 }
 
@@ -208,32 +1136,194 @@ impl Metadata {
         self.wallpapers.as_ref()
     }
 
+    pub fn wallpapers_mut(&mut self) -> impl Iterator<Item = &mut Wallpaper> {
+        self.wallpapers.iter_mut().flatten()
+    }
+
+    /// Keeps only the wallpapers for which `f` returns true.
+    pub fn retain_wallpapers<F: FnMut(&Wallpaper) -> bool>(&mut self, f: F) {
+        if let Some(wallpapers) = &mut self.wallpapers {
+            wallpapers.retain(f);
+        }
+    }
+
     pub fn base(&self) -> Option<&Path> {
         self.base.as_deref()
     }
 
+    pub fn collection(&self) -> Option<&Collection> {
+        self.collection.as_ref()
+    }
+
+    /// Warns about malformed locale tags in translations, or fails on them
+    /// if `strict` is set.
+    pub fn check_locales(&self, strict: bool) -> Result<()> {
+        let authors = self.authors.iter().flatten().map(|a| &a.name);
+        let titles = self
+            .wallpapers
+            .iter()
+            .flatten()
+            .flat_map(|w| std::iter::once(&w.title).chain(&w.description));
+        let collection = self
+            .collection
+            .iter()
+            .flat_map(|c| std::iter::once(&c.name).chain(&c.description));
+        for localized in authors.chain(titles).chain(collection) {
+            for locale in localized.generate_hashmap(|l| l.to_locale())?.into_keys() {
+                if Locale::parse_strict(locale).is_ok() {
+                    continue;
+                }
+                if strict {
+                    bail!("malformed locale {:?}", locale);
+                }
+                warn!("malformed locale {:?}", locale);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the authors credited for `wallpaper`, in the order it lists
+    /// them. Wallpapers not selecting any are credited to all authors.
+    pub fn authors_of(&self, wallpaper: &Wallpaper) -> Result<Vec<&Author>, WpmetaError> {
+        let authors = self.authors.as_deref().unwrap_or_default();
+        let Some(emails) = &wallpaper.authors else {
+            return Ok(authors.iter().collect());
+        };
+        emails
+            .iter()
+            .map(|email| {
+                authors
+                    .iter()
+                    .find(|a| &a.email == email)
+                    .ok_or_else(|| WpmetaError::NoAuthor {
+                        wallpaper: wallpaper.id().to_owned(),
+                        email: email.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Merges translations from a gettext catalog for `locale`.
+    ///
+    /// Wallpaper titles are looked up by their default title, then by id;
+    /// author names are looked up by their default name.
+    pub fn apply_catalog(&mut self, locale: &Locale, catalog: &Catalog) {
+        for author in self.authors.iter_mut().flatten() {
+            translate(&mut author.name, locale, catalog);
+        }
+        for wallpaper in self.wallpapers.iter_mut().flatten() {
+            translate(&mut wallpaper.title, locale, catalog);
+            if let Some(description) = &mut wallpaper.description {
+                translate(description, locale, catalog);
+            }
+            if wallpaper.title.get(locale).is_none() {
+                if let Some(msgstr) = catalog.get(wallpaper.id()) {
+                    wallpaper.title.set(locale.clone(), msgstr.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Rewrites licenses as canonical SPDX expressions where they parse.
+    /// Locales and colors are already normalized by serialization.
+    pub fn normalize(&mut self) {
+        for wallpaper in self.wallpapers.iter_mut().flatten() {
+            if wallpaper.license.is_none() {
+                continue;
+            }
+            if let Ok(license) = wallpaper.canonical_license() {
+                wallpaper.license = Some(license);
+            }
+        }
+    }
+
+    /// Fills in the `[defaults]` this metadata leaves unset from `parent`,
+    /// so they are passed on to the metadata below both.
+    pub fn inherit_defaults(&mut self, parent: &Metadata) {
+        if let Some(parent) = &parent.defaults {
+            self.defaults
+                .get_or_insert_with(Defaults::default)
+                .inherit(parent);
+        }
+    }
+
+    /// Fails if any wallpaper has no license, neither set by itself nor
+    /// through `[defaults]`.
+    pub fn check_licenses(&self) -> Result<()> {
+        for wallpaper in self.wallpapers.iter().flatten() {
+            if wallpaper.license.is_none() {
+                bail!(
+                    "{}: no license given by the wallpaper or [defaults]",
+                    wallpaper.id()
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub fn flatten(&self, base: &Path, parent: Option<&Metadata>) -> Self {
-        let mut authors = to_owned_option(self.authors());
+        let mut authors = to_owned_option(self.authors()).map(merge_authors);
         let mut wallpapers = to_owned_option(self.wallpapers());
+        let mut collection = to_owned_option(self.collection());
+        let mut defaults = self.defaults.clone();
         if let Some(p) = parent {
+            if let Some(parent) = &p.defaults {
+                defaults
+                    .get_or_insert_with(Defaults::default)
+                    .inherit(parent);
+            }
             if authors.is_none() {
                 authors = to_owned_option(p.authors())
+            } else if let (Some(authors), Some(parent_authors)) = (authors.as_mut(), p.authors()) {
+                // Authors also defined by the parent inherit its translations
+                for author in authors.iter_mut() {
+                    if let Some(p) = parent_authors.iter().find(|p| p.email == author.email) {
+                        let mut name = p.name.clone();
+                        name.merge(std::mem::replace(&mut author.name, Localized::new(None)));
+                        author.name = name;
+                        if author.role.is_none() {
+                            author.role = p.role.clone();
+                        }
+                    }
+                }
             }
             if wallpapers.is_none() {
-                wallpapers = to_owned_option(p.wallpapers())
+                wallpapers = to_owned_option(p.wallpapers());
+                // The collection describes the wallpapers it comes with
+                if collection.is_none() {
+                    collection = to_owned_option(p.collection());
+                }
+            }
+        }
+        if let Some(defaults) = &defaults {
+            for wallpaper in wallpapers.iter_mut().flatten() {
+                defaults.apply(wallpaper);
             }
         }
         Self {
             base: Some(base.into()),
+            defaults,
             authors,
             wallpapers,
+            collection,
         }
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use super::Metadata;
+    use hex_color::HexColor;
+    use locale::Locale;
+
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use super::{
+        AspectRatio, ColorShadingType, Metadata, PictureOptions, Resolution, Wallpaper,
+        WallpaperFile, WallpaperFileMeta, WallpaperKind,
+    };
+    use crate::error::WpmetaError;
+    use crate::po::Catalog;
 
     pub static DUMMY_META: &str = r#"
     [[authors]]
@@ -249,10 +1339,629 @@ pub mod test {
     path = "test/example.jpg"
     "#;
 
+    pub static DUMMY_META_MULTIPLE_FILE: &str = r##"
+    [[authors]]
+    email = "yajuu.senpai@example.com"
+    name.default = "Yajuu Senpai"
+    role = "Photography"
+
+    [[wallpapers]]
+    title.default = "Kusa"
+    description.zh-CN = "一片草地"
+    license = "CC-BY-SA-4.0"
+    id = "Kusa"
+    path = ["kusa.jpg", "kusa-dark.jpg"]
+    primary_color = "#023C8880"
+    tags = ["nature"]
+    timed = true
+
+    [[wallpapers]]
+    title.default = "Hana"
+    license = "CC0-1.0"
+    id = "Hana"
+    path = [{ file = "hana-dark.jpg", variant = "normal" }, { file = "night.jpg", variant = "dark" }]
+    option = "zoom"
+    authors = ["yajuu.senpai@example.com"]
+    resolutions = ["hana-1080p.jpg"]
+
+    [collection]
+    id = "Plants"
+    name.default = "Plants"
+    "##;
+
+    #[test]
+    fn test_round_trip() {
+        for src in [DUMMY_META, DUMMY_META_MULTIPLE_FILE] {
+            let meta = toml::from_str::<Metadata>(src).unwrap();
+            let serialized = toml::to_string(&meta).unwrap();
+            assert_eq!(toml::from_str::<Metadata>(&serialized).unwrap(), meta);
+        }
+    }
+
     #[test]
     fn test_de() {
         let dummy_meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
         assert_eq!(dummy_meta.authors().unwrap().len(), 1);
         assert_eq!(dummy_meta.wallpapers().unwrap().len(), 1);
     }
+
+    #[test]
+    fn test_apply_catalog() {
+        let mut dummy_meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        let catalog = Catalog::parse(crate::po::test::DUMMY_PO).unwrap();
+        let zh_cn = Locale::new("zh-CN");
+        dummy_meta.apply_catalog(&zh_cn, &catalog);
+        let wallpaper = &dummy_meta.wallpapers().unwrap()[0];
+        assert_eq!(wallpaper.titles().get(&zh_cn).unwrap(), "草");
+        assert_eq!(wallpaper.titles().len(), 3);
+        // Existing translations are kept, fuzzy entries are skipped
+        let author = &dummy_meta.authors().unwrap()[0];
+        assert_eq!(author.name().get(&zh_cn).unwrap(), "野兽先辈");
+    }
+
+    #[test]
+    fn test_license() {
+        let mut dummy_meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        let wallpaper = &mut dummy_meta.wallpapers.as_mut().unwrap()[0];
+        assert!(wallpaper.canonical_license().is_err());
+        assert!(wallpaper.check_license(false).is_ok());
+        assert_eq!(
+            wallpaper.check_license(true).unwrap_err().to_string(),
+            "Kusa: invalid SPDX license expression \"CC BY-SA 4.0\": CC BY-SA 4.0\n^^ unknown term"
+        );
+        wallpaper.license = Some("GPL-2.0+".into());
+        assert_eq!(wallpaper.canonical_license().unwrap(), "GPL-2.0-or-later");
+        assert!(wallpaper.check_license(true).is_ok());
+        wallpaper.license = Some("MIT".into());
+        assert_eq!(wallpaper.canonical_license().unwrap(), "MIT");
+    }
+
+    #[test]
+    fn test_merge_authors() {
+        let parent = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        let child = toml::from_str::<Metadata>(
+            r#"
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.default = "Senpai"
+            name.ja-JP = "野獣先輩"
+            role = "Photography"
+
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.zh-TW = "野獸先輩"
+            "#,
+        )
+        .unwrap();
+        let flattened = child.flatten(Path::new("."), Some(&parent));
+        let authors = flattened.authors().unwrap();
+        assert_eq!(authors.len(), 1);
+        let name = authors[0].name();
+        assert_eq!(name.get_default().unwrap(), "Senpai");
+        assert_eq!(name.get(&Locale::new("zh-CN")).unwrap(), "野兽先辈");
+        assert_eq!(name.get(&Locale::new("ja-JP")).unwrap(), "野獣先輩");
+        assert_eq!(name.get(&Locale::new("zh-TW")).unwrap(), "野獸先輩");
+        assert_eq!(authors[0].role(), Some("Photography"));
+        assert_eq!(flattened.wallpapers().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_inherit_defaults() {
+        let grandparent = toml::from_str::<Metadata>(
+            r##"
+            [defaults]
+            license = "CC0-1.0"
+            option = "zoom"
+            primary_color = "#000000"
+            "##,
+        )
+        .unwrap();
+        let mut parent = toml::from_str::<Metadata>(
+            r##"
+            [defaults]
+            license = "CC-BY-SA-4.0"
+            secondary_color = "#FFFFFF"
+            "##,
+        )
+        .unwrap();
+        parent.inherit_defaults(&grandparent);
+        let child = toml::from_str::<Metadata>(
+            r##"
+            [[wallpapers]]
+            title.default = "Kusa"
+            id = "Kusa"
+            path = "kusa.png"
+            option = "centered"
+
+            [[wallpapers]]
+            title.default = "Hana"
+            id = "Hana"
+            path = "hana.png"
+            license = "MIT"
+            "##,
+        )
+        .unwrap();
+        assert!(child.check_licenses().is_err());
+        let flattened = child.flatten(Path::new("."), Some(&parent));
+        flattened.check_licenses().unwrap();
+        let wallpapers = flattened.wallpapers().unwrap();
+        // The closest defaults win, and the wallpaper over all of them
+        assert_eq!(wallpapers[0].license(), "CC-BY-SA-4.0");
+        assert_eq!(wallpapers[0].option(), PictureOptions::Centered);
+        assert_eq!(
+            wallpapers[0].colors(),
+            (HexColor::rgb(0, 0, 0), HexColor::rgb(255, 255, 255))
+        );
+        assert_eq!(wallpapers[1].license(), "MIT");
+        assert_eq!(wallpapers[1].option(), PictureOptions::Zoom);
+        assert_eq!(wallpapers[1].shade_type(), ColorShadingType::Solid);
+        // Defaults stay out of the file the wallpaper was read from
+        assert!(!toml::to_string(&child).unwrap().contains("CC-BY-SA-4.0"));
+    }
+
+    #[test]
+    fn test_resolution_from_str() {
+        let parse = |s: &str| s.parse::<Resolution>();
+        let full_hd = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(parse("1920x1080").unwrap(), full_hd);
+        assert_eq!(parse("1920X1080").unwrap(), full_hd);
+        assert_eq!(parse(" 1920 x 1080 ").unwrap(), full_hd);
+        assert_eq!(
+            parse("500,500").unwrap(),
+            Resolution {
+                width: 500,
+                height: 500
+            }
+        );
+        for invalid in ["0x0", "1920x0", "-1920x1080", "abc", "1920", "1920x", "axb"] {
+            assert!(parse(invalid).is_err(), "{} should be invalid", invalid);
+        }
+        assert!(matches!(
+            parse("abc"),
+            Err(WpmetaError::InvalidResolution { input, .. }) if input == "abc"
+        ));
+        assert!(parse("0x0").unwrap_err().to_string().contains("positive"));
+    }
+
+    #[test]
+    fn test_resolution_conversions() {
+        let full_hd = Resolution::new(1920, 1080).unwrap();
+        assert_eq!(full_hd.to_string(), "1920x1080");
+        assert_eq!(Resolution::try_from("1920x1080").unwrap(), full_hd);
+        assert_eq!(Resolution::try_from(full_hd.to_string()).unwrap(), full_hd);
+        assert_eq!(Resolution::try_from("1920,1080").unwrap(), full_hd);
+        assert!(Resolution::try_from("1920x0").is_err());
+        for (width, height) in [(0, 0), (1920, 0), (0, 1080)] {
+            assert!(matches!(
+                Resolution::new(width, height),
+                Err(WpmetaError::InvalidResolution { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_resolution_fit_within() {
+        let limit = Resolution {
+            width: 500,
+            height: 500,
+        };
+        let landscape = Resolution {
+            width: 7680,
+            height: 4320,
+        };
+        let portrait = Resolution {
+            width: 1080,
+            height: 1920,
+        };
+        let small = Resolution {
+            width: 100,
+            height: 50,
+        };
+        assert_eq!(
+            landscape.fit_within(limit),
+            Resolution {
+                width: 500,
+                height: 281
+            }
+        );
+        assert_eq!(
+            portrait.fit_within(limit),
+            Resolution {
+                width: 281,
+                height: 500
+            }
+        );
+        assert_eq!(small.fit_within(limit), small);
+        assert_eq!("500,500".parse::<Resolution>().unwrap(), limit);
+    }
+
+    #[test]
+    fn test_named_colors() {
+        let meta = format!(
+            "{}primary_color = \"navy\"\nsecondary_color = \"#5789CA\"\n",
+            DUMMY_META
+        );
+        let meta = toml::from_str::<Metadata>(&meta).unwrap();
+        let wallpaper = &meta.wallpapers().unwrap()[0];
+        assert_eq!(
+            wallpaper.colors(),
+            (HexColor::rgb(0, 0, 128), HexColor::rgb(0x57, 0x89, 0xCA))
+        );
+        let meta = format!("{}primary_color = \"grassgreen\"\n", DUMMY_META);
+        assert!(toml::from_str::<Metadata>(&meta).is_err());
+    }
+
+    #[test]
+    fn test_contrast() {
+        let mut dummy_meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        let wallpaper = &mut dummy_meta.wallpapers.as_mut().unwrap()[0];
+        assert!(wallpaper.check_contrast(true).is_ok());
+        wallpaper.primary_color = Some(HexColor::rgb(0, 0, 0));
+        wallpaper.secondary_color = Some(HexColor::rgb(16, 16, 16));
+        assert!(wallpaper.check_contrast(false).is_ok());
+        assert!(wallpaper.check_contrast(true).is_err());
+        wallpaper.secondary_color = Some(HexColor::rgb(255, 255, 255));
+        assert!(wallpaper.check_contrast(true).is_ok());
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        let ratio = |width, height| AspectRatio::new(width, height).unwrap().to_string();
+        assert_eq!(ratio(1920, 1080), "16:9");
+        assert_eq!(ratio(2560, 1080), "64:27");
+        assert_eq!(ratio(1080, 1920), "9:16");
+        assert_eq!(ratio(7, 7), "1:1");
+        assert!(AspectRatio::new(0, 9).is_err());
+        assert_eq!(
+            "32:18".parse::<AspectRatio>().unwrap(),
+            AspectRatio::new(16, 9).unwrap()
+        );
+        for invalid in ["16x9", "16:", "-16:9", "16:0"] {
+            assert!(matches!(
+                invalid.parse::<AspectRatio>(),
+                Err(WpmetaError::InvalidAspectRatio { .. })
+            ));
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        image::RgbImage::new(64, 36)
+            .save(dir.path().join("kusa.png"))
+            .unwrap();
+        let mut wallpaper = toml::from_str::<Wallpaper>(
+            r#"
+            title.default = "Kusa"
+            license = "CC0-1.0"
+            id = "Kusa"
+            path = "kusa.png"
+            aspect_ratio = "21:9"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(wallpaper.aspect_ratio(), AspectRatio::new(21, 9).ok());
+        // The image wins over a mismatching expectation
+        wallpaper.tag_aspect_ratio(dir.path()).unwrap();
+        assert_eq!(wallpaper.aspect_ratio(), AspectRatio::new(16, 9).ok());
+    }
+
+    #[test]
+    fn test_check_dark_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, c) in [("light.png", 230), ("night.png", 20)] {
+            image::RgbImage::from_pixel(32, 18, image::Rgb([c, c, c]))
+                .save(dir.path().join(name))
+                .unwrap();
+        }
+        let wallpaper = |path: &str| {
+            toml::from_str::<Wallpaper>(&format!(
+                "title.default = \"Kusa\"\nlicense = \"CC0-1.0\"\nid = \"Kusa\"\npath = {}",
+                path
+            ))
+            .unwrap()
+        };
+        let good =
+            wallpaper(r#"[{ file = "light.png" }, { file = "night.png", variant = "dark" }]"#);
+        assert!(good.check_dark_variant(dir.path(), true).is_ok());
+        let swapped =
+            wallpaper(r#"[{ file = "night.png" }, { file = "light.png", variant = "dark" }]"#);
+        assert!(swapped.check_dark_variant(dir.path(), false).is_ok());
+        assert!(swapped.check_dark_variant(dir.path(), true).is_err());
+        // Nothing to compare without a dark variant
+        let single = wallpaper(r#""light.png""#);
+        assert!(single.check_dark_variant(dir.path(), true).is_ok());
+    }
+
+    #[test]
+    fn test_swaybg_mode() {
+        assert_eq!(PictureOptions::None.to_swaybg_mode(), "solid_color");
+        assert_eq!(PictureOptions::Wallpaper.to_swaybg_mode(), "tile");
+        assert_eq!(PictureOptions::Centered.to_swaybg_mode(), "center");
+        assert_eq!(PictureOptions::Scaled.to_swaybg_mode(), "fit");
+        assert_eq!(PictureOptions::Stretched.to_swaybg_mode(), "stretch");
+        assert_eq!(PictureOptions::Zoom.to_swaybg_mode(), "fill");
+        assert_eq!(PictureOptions::Spanned.to_swaybg_mode(), "fill");
+    }
+
+    #[test]
+    fn test_install_path() {
+        let meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        let wallpaper = &meta.wallpapers().unwrap()[0];
+        assert_eq!(wallpaper.package_dir(), Path::new("wallpapers/Kusa"));
+        let with = |path: &str| {
+            toml::from_str::<Metadata>(&format!("{}install_path = {:?}\n", DUMMY_META, path))
+        };
+        let meta = with("backgrounds/grass").unwrap();
+        let wallpaper = &meta.wallpapers().unwrap()[0];
+        assert_eq!(wallpaper.package_dir(), Path::new("backgrounds/grass"));
+        for path in [
+            "",
+            "/usr/share/backgrounds",
+            "../backgrounds",
+            "backgrounds/../..",
+        ] {
+            assert!(with(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_invalid_id() {
+        let with = |id: &str| {
+            toml::from_str::<Metadata>(
+                &DUMMY_META.replace(r#"id = "Kusa""#, &format!("id = {:?}", id)),
+            )
+        };
+        let meta = with("Kusa Grass-2").unwrap();
+        assert_eq!(meta.wallpapers().unwrap()[0].id(), "Kusa Grass-2");
+        for id in ["", "  ", "kusa/grass", "..", "kusa\\grass", "kusa\n"] {
+            let err = with(id).unwrap_err().to_string();
+            assert!(err.contains("invalid id"), "{:?}: {}", id, err);
+        }
+    }
+
+    #[test]
+    fn test_kind_from_file_name() {
+        for (name, kind) in [
+            ("foo.jpg", WallpaperKind::Normal),
+            ("foo.dark.jpg", WallpaperKind::Dark),
+            ("foo.bar.dark.png", WallpaperKind::Dark),
+            ("foo-Dark.png", WallpaperKind::Dark),
+            ("foo.dark.bar.png", WallpaperKind::Normal),
+            ("darkness.jpg", WallpaperKind::Normal),
+        ] {
+            assert_eq!(
+                WallpaperKind::from_file_name(Path::new(name)),
+                kind,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_wallpaper_file_new() {
+        let file = WallpaperFile::new("Kusa", PathBuf::from("not/staged/kusa.png"));
+        assert_eq!(file.id(), "Kusa");
+        assert_eq!(file.src(), Path::new("not/staged/kusa.png"));
+        assert_eq!(file.dark_src(), None);
+    }
+
+    #[test]
+    fn test_gnome_str() {
+        assert_eq!(PictureOptions::None.to_gnome_str(), "none");
+        assert_eq!(PictureOptions::Wallpaper.to_gnome_str(), "wallpaper");
+        assert_eq!(PictureOptions::Centered.to_gnome_str(), "centered");
+        assert_eq!(PictureOptions::Scaled.to_gnome_str(), "scaled");
+        assert_eq!(PictureOptions::Stretched.to_gnome_str(), "stretched");
+        assert_eq!(PictureOptions::Zoom.to_gnome_str(), "zoom");
+        assert_eq!(PictureOptions::Spanned.to_gnome_str(), "spanned");
+        assert_eq!(ColorShadingType::Horizontal.to_gnome_str(), "horizontal");
+        assert_eq!(ColorShadingType::Vertical.to_gnome_str(), "vertical");
+        assert_eq!(ColorShadingType::Solid.to_gnome_str(), "solid");
+    }
+
+    #[test]
+    fn test_check_resolution() {
+        let dir = tempfile::tempdir().unwrap();
+        let min = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+        let wallpaper = |width, height| {
+            let name = format!("{}x{}.png", width, height);
+            image::RgbImage::new(width, height)
+                .save(dir.path().join(&name))
+                .unwrap();
+            toml::from_str::<Wallpaper>(&format!(
+                r#"
+                title.default = "Kusa"
+                license = "CC0-1.0"
+                id = "Kusa{}"
+                path = "{}"
+                "#,
+                width, name
+            ))
+            .unwrap()
+        };
+        let err = wallpaper(100, 100)
+            .check_resolution(dir.path(), min)
+            .unwrap_err();
+        assert!(err.to_string().contains("100x100"));
+        wallpaper(3840, 2160)
+            .check_resolution(dir.path(), min)
+            .unwrap();
+        // Both dimensions must meet the minimum
+        assert!(wallpaper(3840, 1000)
+            .check_resolution(dir.path(), min)
+            .is_err());
+    }
+
+    #[test]
+    fn test_oriented_dimensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = crate::orientation::test::oriented_jpeg(64, 36, 6);
+        std::fs::write(dir.path().join("kusa.jpg"), jpeg).unwrap();
+        let meta = WallpaperFileMeta::new(
+            Path::new("wallpapers/Kusa"),
+            &dir.path().join("kusa.jpg"),
+            WallpaperKind::Normal,
+        )
+        .unwrap();
+        assert_eq!(meta.dimensions(), (36, 64));
+        assert_eq!(
+            meta.target(),
+            Path::new("wallpapers/Kusa/contents/images/36x64.jpg")
+        );
+    }
+
+    #[test]
+    fn test_best_source_for() {
+        let dir = tempfile::tempdir().unwrap();
+        // 1080p, 4K and 8K scaled down by 10
+        for (name, width, height) in [
+            ("kusa-1080p.png", 192, 108),
+            ("kusa-4k.png", 384, 216),
+            ("kusa-8k.png", 768, 432),
+        ] {
+            image::RgbImage::new(width, height)
+                .save(dir.path().join(name))
+                .unwrap();
+        }
+        let wallpaper = toml::from_str::<Wallpaper>(
+            r#"
+            title.default = "Kusa"
+            license = "CC0-1.0"
+            id = "Kusa"
+            path = "kusa-1080p.png"
+            resolutions = ["kusa-8k.png", "kusa-4k.png"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(wallpaper.sources().count(), 3);
+        let best = |width, height| {
+            wallpaper
+                .best_source_for(dir.path(), Resolution { width, height })
+                .unwrap()
+                .src()
+        };
+        assert_eq!(best(200, 200), Path::new("kusa-4k.png"));
+        assert_eq!(best(100, 100), Path::new("kusa-1080p.png"));
+        assert_eq!(best(384, 216), Path::new("kusa-4k.png"));
+        // Nothing is large enough, so the largest is taken
+        assert_eq!(best(1000, 1000), Path::new("kusa-8k.png"));
+    }
+
+    #[test]
+    fn test_image_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let new = |name: &str| {
+            WallpaperFileMeta::new(
+                Path::new("wallpapers/Kusa"),
+                &dir.path().join(name),
+                WallpaperKind::Normal,
+            )
+        };
+        assert!(matches!(new("kusa.jpg"), Err(WpmetaError::MissingFile(_))));
+        std::fs::write(dir.path().join("kusa.txt"), "not an image").unwrap();
+        assert!(matches!(
+            new("kusa.txt"),
+            Err(WpmetaError::UnknownFormat(_))
+        ));
+        // A PNG signature with nothing after it
+        std::fs::write(dir.path().join("kusa.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        assert!(matches!(
+            new("kusa.png"),
+            Err(WpmetaError::ImageDecode { .. })
+        ));
+        // A GIF header declaring a 0x0 canvas, followed by the trailer
+        std::fs::write(
+            dir.path().join("kusa.gif"),
+            b"GIF89a\x00\x00\x00\x00\x00\x00\x00\x3b",
+        )
+        .unwrap();
+        assert!(matches!(new("kusa.gif"), Err(WpmetaError::EmptyImage(_))));
+    }
+
+    #[test]
+    fn test_image_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        image::RgbImage::new(64, 36)
+            .save(dir.path().join("kusa.png"))
+            .unwrap();
+        let file = WallpaperFile::new("Kusa", "kusa.png".into());
+        let img = file.image(dir.path()).unwrap();
+        assert!(Arc::ptr_eq(&img, &file.image(dir.path()).unwrap()));
+        file.release_image();
+        let reloaded = file.image(dir.path()).unwrap();
+        assert!(!Arc::ptr_eq(&img, &reloaded));
+        assert_eq!(*img, *reloaded);
+    }
+
+    #[test]
+    fn test_unknown_author() {
+        let mut meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        let wallpaper = &mut meta.wallpapers.as_mut().unwrap()[0];
+        wallpaper.authors = Some(vec!["nobody@example.com".into()]);
+        let wallpaper = meta.wallpapers().unwrap()[0].clone();
+        assert!(matches!(
+            meta.authors_of(&wallpaper),
+            Err(WpmetaError::NoAuthor { email, .. }) if email == "nobody@example.com"
+        ));
+    }
+
+    #[test]
+    fn test_wallpaper_paths() {
+        let wallpaper = |path: &str| {
+            toml::from_str::<Wallpaper>(&format!(
+                "title.default = \"Kusa\"\nlicense = \"CC0-1.0\"\nid = \"Kusa\"\npath = {}",
+                path
+            ))
+        };
+        let paths = |path: &str| {
+            let w = wallpaper(path).unwrap();
+            (w.src().to_owned(), w.dark_src().map(Path::to_owned))
+        };
+        // A single path is never a dark variant
+        assert_eq!(paths(r#""kusa-dark.jpg""#), ("kusa-dark.jpg".into(), None));
+        assert_eq!(
+            paths(r#"["kusa.jpg", "kusa-dark.jpg"]"#),
+            ("kusa.jpg".into(), Some("kusa-dark.jpg".into()))
+        );
+        assert_eq!(
+            paths(r#"["darkforest.jpg", "Forest_Dark.png"]"#),
+            ("darkforest.jpg".into(), Some("Forest_Dark.png".into()))
+        );
+        assert_eq!(
+            paths(
+                r#"[{ file = "kusa-dark.jpg", variant = "normal" }, { file = "night.jpg", variant = "dark" }]"#
+            ),
+            ("kusa-dark.jpg".into(), Some("night.jpg".into()))
+        );
+        assert_eq!(
+            paths(r#"[{ file = "kusa.jpg" }]"#),
+            ("kusa.jpg".into(), None)
+        );
+        assert!(wallpaper(r#"["kusa.jpg", "hana.jpg"]"#).is_err());
+        // A lone dark variant is promoted to the normal one
+        assert_eq!(
+            paths(r#"["kusa-dark.jpg"]"#),
+            ("kusa-dark.jpg".into(), None)
+        );
+        assert_eq!(
+            paths(r#"[{ file = "night.jpg", variant = "dark" }]"#),
+            ("night.jpg".into(), None)
+        );
+        assert!(wallpaper("[]").is_err());
+    }
+
+    #[test]
+    fn test_check_locales() {
+        let meta = toml::from_str::<Metadata>(DUMMY_META).unwrap();
+        meta.check_locales(true).unwrap();
+        let meta =
+            toml::from_str::<Metadata>(&format!("{}title.\"!!!\" = \"?\"\n", DUMMY_META)).unwrap();
+        meta.check_locales(false).unwrap();
+        assert!(meta.check_locales(true).is_err());
+    }
 }
@@ -0,0 +1,125 @@
+use eyre::Result;
+use image::io::Reader;
+use image::DynamicImage;
+use log::debug;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::error::WpmetaError;
+
+/// Reads the EXIF orientation of the image at `path`, 1 (upright) if it
+/// has none.
+pub fn read(path: &Path) -> u32 {
+    let exif = File::open(path).ok().and_then(|file| {
+        exif::Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()
+    });
+    let orientation = exif
+        .as_ref()
+        .and_then(|e| e.get_field(exif::Tag::Orientation, exif::In::PRIMARY))
+        .and_then(|f| f.value.get_uint(0));
+    match orientation {
+        Some(o @ 1..=8) => o,
+        Some(o) => {
+            debug!("{}: ignoring invalid orientation {}", path.display(), o);
+            1
+        }
+        None => 1,
+    }
+}
+
+/// Transforms `img` as displayed with the EXIF `orientation`.
+pub fn apply(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Decodes the image at `path` in its displayed orientation.
+pub fn open(path: &Path) -> Result<DynamicImage, WpmetaError> {
+    let img = Reader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| WpmetaError::io(path, e))?
+        .decode()
+        .map_err(|source| WpmetaError::ImageDecode {
+            path: path.to_owned(),
+            source,
+        })?;
+    #[cfg(test)]
+    test::DECODED.lock().unwrap().push(path.to_owned());
+    Ok(apply(img, read(path)))
+}
+
+#[cfg(test)]
+pub mod test {
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat, Rgb, RgbImage};
+
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use super::{apply, open, read};
+
+    /// Every image decoded by [`open`], for tests to count decodes of the
+    /// files they created.
+    pub static DECODED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+    /// Encodes a `width`x`height` JPEG tagged with the EXIF `orientation`.
+    pub fn oriented_jpeg(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([2, 60, 136])))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // Little endian TIFF header with a single IFD holding the orientation
+        let mut tiff = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(&tiff);
+        let mut ret = jpeg[..2].to_vec();
+        ret.extend_from_slice(&[0xff, 0xe1]);
+        ret.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        ret.extend_from_slice(&app1);
+        ret.extend_from_slice(&jpeg[2..]);
+        ret
+    }
+
+    #[test]
+    fn test_orientation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kusa.jpg");
+        std::fs::write(&path, oriented_jpeg(64, 36, 6)).unwrap();
+        assert_eq!(read(&path), 6);
+        assert_eq!(open(&path).unwrap().dimensions(), (36, 64));
+
+        std::fs::write(&path, oriented_jpeg(64, 36, 3)).unwrap();
+        assert_eq!(open(&path).unwrap().dimensions(), (64, 36));
+
+        // No EXIF at all
+        RgbImage::new(64, 36).save(&path).unwrap();
+        assert_eq!(read(&path), 1);
+    }
+
+    #[test]
+    fn test_apply() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(2, 1, |x, _| Rgb([x as u8, 0, 0])));
+        for orientation in 1..=8 {
+            let oriented = apply(img.clone(), orientation);
+            let expected = if orientation <= 4 { (2, 1) } else { (1, 2) };
+            assert_eq!(oriented.dimensions(), expected);
+        }
+        assert_eq!(apply(img.clone(), 2).get_pixel(0, 0)[0], 1);
+        // Transposing keeps the first pixel in place
+        assert_eq!(apply(img, 5).get_pixel(0, 0)[0], 0);
+    }
+}
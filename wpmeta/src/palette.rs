@@ -0,0 +1,437 @@
+use eyre::{eyre, Result};
+use hex_color::HexColor;
+use image::{imageops, DynamicImage, RgbImage};
+
+/// How far a derived accent is mixed towards black or white.
+static ACCENT_SHIFT: f64 = 0.4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaletteConfig {
+    /// Number of colors the image is quantized to
+    pub palette_size: u16,
+    /// Size of the box the image is downscaled into before quantizing
+    pub downscale: u32,
+    /// Number of distinct colors to rank
+    pub desired: usize,
+    /// Drop colors too gray or too rare to represent the image
+    pub filter_quality: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Swatch {
+    rgb: [u8; 3],
+    population: usize,
+}
+
+impl Default for PaletteConfig {
+    fn default() -> Self {
+        Self {
+            palette_size: 128,
+            downscale: 128,
+            desired: 8,
+            filter_quality: true,
+        }
+    }
+}
+
+impl Swatch {
+    /// Chroma in the range of 0 to 100.
+    fn chroma(&self) -> f64 {
+        let max = self.rgb.iter().max().copied().unwrap_or_default();
+        let min = self.rgb.iter().min().copied().unwrap_or_default();
+        f64::from(max - min) / 255.0 * 100.0
+    }
+
+    /// Hue in degrees, 0 for grays.
+    fn hue(&self) -> f64 {
+        let [r, g, b] = self.rgb.map(f64::from);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0.0 {
+            return 0.0;
+        }
+        let hue = if max == r {
+            ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        };
+        hue * 60.0
+    }
+
+    fn to_hex(self) -> HexColor {
+        let [r, g, b] = self.rgb;
+        HexColor::rgb(r, g, b)
+    }
+}
+
+fn hue_distance(a: f64, b: f64) -> f64 {
+    let d = (a - b).abs();
+    d.min(360.0 - d)
+}
+
+/// Quantizes `pixels` with median cut, splitting the box with the widest
+/// channel range until there are `size` boxes or nothing left to split.
+fn median_cut(pixels: Vec<[u8; 3]>, size: usize) -> Vec<Swatch> {
+    fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+        (0..3)
+            .map(|c| {
+                let max = pixels.iter().map(|p| p[c]).max().unwrap_or_default();
+                let min = pixels.iter().map(|p| p[c]).min().unwrap_or_default();
+                (c, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap_or_default()
+    }
+
+    let mut boxes = vec![pixels];
+    while boxes.len() < size {
+        let Some((idx, channel)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i, widest_channel(b)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(i, (_, range))| (*range, std::cmp::Reverse(*i)))
+            .map(|(i, (c, _))| (i, c))
+        else {
+            break;
+        };
+        let mut pixels = boxes.swap_remove(idx);
+        pixels.sort_unstable_by_key(|p| p[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+    boxes
+        .into_iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| {
+            let mut sum = [0usize; 3];
+            for p in &b {
+                for c in 0..3 {
+                    sum[c] += usize::from(p[c]);
+                }
+            }
+            Swatch {
+                rgb: sum.map(|s| (s / b.len()) as u8),
+                population: b.len(),
+            }
+        })
+        .collect()
+}
+
+/// Ranks swatches after Material's scoring: common colors with high
+/// chroma first, skipping colors with hues too close to better ones.
+fn score(swatches: &[Swatch], config: &PaletteConfig) -> Vec<Swatch> {
+    let total: usize = swatches.iter().map(|s| s.population).sum();
+    // Like Material, a color's weight includes colors of similar hues
+    let hue_proportion = |swatch: &Swatch| {
+        let population: usize = swatches
+            .iter()
+            .filter(|s| s.chroma() >= 5.0 && hue_distance(s.hue(), swatch.hue()) <= 15.0)
+            .map(|s| s.population)
+            .sum();
+        population as f64 / total as f64
+    };
+    let mut scored: Vec<(f64, Swatch)> = swatches
+        .iter()
+        .map(|s| (hue_proportion(s), *s))
+        .filter(|(proportion, s)| {
+            !config.filter_quality || (s.chroma() >= 5.0 && *proportion >= 0.01)
+        })
+        .map(|(proportion, s)| {
+            let proportion = proportion * 100.0 * 0.7;
+            let chroma = s.chroma() - 48.0;
+            let chroma = chroma * if chroma < 0.0 { 0.1 } else { 0.3 };
+            (proportion + chroma, s)
+        })
+        .collect();
+    // Stable sort so equal scores keep the quantizer's order
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut chosen: Vec<Swatch> = Vec::new();
+    for threshold in (15..=90).rev().step_by(15) {
+        chosen.clear();
+        for (_, swatch) in &scored {
+            if chosen
+                .iter()
+                .all(|c| hue_distance(c.hue(), swatch.hue()) >= f64::from(threshold))
+            {
+                chosen.push(*swatch);
+            }
+            if chosen.len() >= config.desired {
+                break;
+            }
+        }
+        if chosen.len() >= config.desired {
+            break;
+        }
+    }
+    chosen
+}
+
+/// Size of the thumbnail `DynamicImage::thumbnail` makes of a
+/// `width`x`height` image, fitted into a `size`x`size` box.
+fn thumbnail_size(width: u32, height: u32, size: u32) -> (u32, u32) {
+    let ratio = f64::min(
+        f64::from(size) / f64::from(width),
+        f64::from(size) / f64::from(height),
+    );
+    let scale = |len: u32| ((f64::from(len) * ratio).round() as u32).max(1);
+    (scale(width), scale(height))
+}
+
+fn quantize_thumbnail(thumbnail: &RgbImage, config: &PaletteConfig) -> Vec<Swatch> {
+    let pixels = thumbnail.pixels().map(|p| p.0).collect();
+    median_cut(pixels, usize::from(config.palette_size.max(1)))
+}
+
+fn quantize_rgb8(image: &RgbImage, config: &PaletteConfig) -> Vec<Swatch> {
+    let (width, height) = thumbnail_size(image.width(), image.height(), config.downscale);
+    quantize_thumbnail(&imageops::thumbnail(image, width, height), config)
+}
+
+fn quantize(image: &DynamicImage, config: &PaletteConfig) -> Vec<Swatch> {
+    match image {
+        DynamicImage::ImageRgb8(image) => quantize_rgb8(image, config),
+        // Downscaled before the conversion, which is cheaper on the thumbnail
+        _ => {
+            let thumbnail = image.thumbnail(config.downscale, config.downscale);
+            quantize_thumbnail(&thumbnail.to_rgb8(), config)
+        }
+    }
+}
+
+/// WCAG relative luminance of `color`.
+fn relative_luminance(color: HexColor) -> f64 {
+    let linear = |c: u8| {
+        let c = f64::from(c) / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(color.r) + 0.7152 * linear(color.g) + 0.0722 * linear(color.b)
+}
+
+/// Average relative luminance of `image`, from 0.0 for black to 1.0 for
+/// white.
+pub fn mean_luminance(image: &DynamicImage) -> f64 {
+    let thumbnail = image.thumbnail(64, 64).to_rgb8();
+    let total: f64 = thumbnail
+        .pixels()
+        .map(|p| relative_luminance(HexColor::rgb(p[0], p[1], p[2])))
+        .sum();
+    total / f64::from(thumbnail.width() * thumbnail.height()).max(1.0)
+}
+
+/// WCAG contrast ratio between two colors, from 1.0 to 21.0.
+pub fn contrast_ratio(a: HexColor, b: HexColor) -> f64 {
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Extracts up to `config.desired` colors of `image` in rank order.
+pub fn extract_ranked_colors_with(
+    image: &DynamicImage,
+    config: &PaletteConfig,
+) -> Result<Vec<HexColor>> {
+    rank(quantize(image, config), config)
+}
+
+fn rank(swatches: Vec<Swatch>, config: &PaletteConfig) -> Result<Vec<HexColor>> {
+    let ranked = score(&swatches, config);
+    if ranked.is_empty() {
+        // Images without any usable color fall back to the most common one
+        let dominant = swatches
+            .iter()
+            .max_by_key(|s| s.population)
+            .ok_or_else(|| eyre!("cannot extract colors from an empty image"))?;
+        return Ok(vec![dominant.to_hex()]);
+    }
+    Ok(ranked.into_iter().map(Swatch::to_hex).collect())
+}
+
+/// Extracts up to `count` colors of `image` in rank order, fewer if the
+/// image doesn't have that many distinct colors.
+pub fn extract_ranked_colors(image: &DynamicImage, count: usize) -> Result<Vec<HexColor>> {
+    let config = PaletteConfig {
+        desired: count,
+        ..Default::default()
+    };
+    extract_ranked_colors_with(image, &config)
+}
+
+/// Derives an accent from `color` for images without a second color, by
+/// mixing it with white if it is dark or with black if it is light.
+fn shifted_accent(color: HexColor) -> HexColor {
+    // Luminance with the same contrast against black and white
+    let target = if relative_luminance(color) < 0.179 {
+        255.0
+    } else {
+        0.0
+    };
+    let mix = |c: u8| (f64::from(c) + (target - f64::from(c)) * ACCENT_SHIFT).round() as u8;
+    HexColor::rgb(mix(color.r), mix(color.g), mix(color.b))
+}
+
+/// Extracts the primary and accent colors of `image` using `config`.
+pub fn extract_colors_with(
+    image: &DynamicImage,
+    config: &PaletteConfig,
+) -> Result<(HexColor, HexColor)> {
+    primary_and_accent(extract_ranked_colors_with(image, config)?)
+}
+
+fn primary_and_accent(ranked: Vec<HexColor>) -> Result<(HexColor, HexColor)> {
+    let primary = *ranked
+        .first()
+        .ok_or_else(|| eyre!("no colors extracted from image"))?;
+    let accent = ranked
+        .get(1)
+        .copied()
+        .unwrap_or_else(|| shifted_accent(primary));
+    Ok((primary, accent))
+}
+
+/// Extracts the primary and accent colors of `image`.
+pub fn extract_colors(image: &DynamicImage) -> Result<(HexColor, HexColor)> {
+    extract_colors_with(image, &PaletteConfig::default())
+}
+
+/// Like [`extract_colors`], for callers that already have RGB pixels.
+pub fn extract_colors_rgb8(image: &RgbImage) -> Result<(HexColor, HexColor)> {
+    let config = PaletteConfig::default();
+    primary_and_accent(rank(quantize_rgb8(image, &config), &config)?)
+}
+
+#[cfg(test)]
+mod test {
+    use hex_color::HexColor;
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    use super::{
+        contrast_ratio, extract_colors, extract_colors_rgb8, extract_colors_with,
+        extract_ranked_colors, mean_luminance, thumbnail_size, PaletteConfig,
+    };
+
+    /// Mostly blue with an orange stripe and some gray noise.
+    pub fn dummy_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(160, 90, |x, y| {
+            if y < 20 {
+                Rgb([240, 120, 16])
+            } else if (x + y) % 7 == 0 {
+                Rgb([128, 128, 128])
+            } else {
+                Rgb([2, 60, 136])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_mean_luminance() {
+        let solid = |c| DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 9, Rgb([c, c, c])));
+        assert_eq!(mean_luminance(&solid(0)), 0.0);
+        assert!((mean_luminance(&solid(255)) - 1.0).abs() < 1e-9);
+        let image = dummy_image();
+        assert!(mean_luminance(&image) > mean_luminance(&solid(16)));
+        assert!(mean_luminance(&image) < mean_luminance(&solid(240)));
+    }
+
+    #[test]
+    fn test_extract_colors() {
+        let image = dummy_image();
+        let colors = extract_colors(&image).unwrap();
+        assert_eq!(colors, extract_colors(&image).unwrap());
+        assert_eq!(
+            colors,
+            (HexColor::rgb(2, 60, 136), HexColor::rgb(240, 120, 16))
+        );
+    }
+
+    #[test]
+    fn test_extract_colors_rgb8() {
+        let image = dummy_image();
+        let rgb = image.to_rgb8();
+        assert_eq!(
+            extract_colors_rgb8(&rgb).unwrap(),
+            extract_colors(&image).unwrap()
+        );
+        // Other pixel formats take the same thumbnail
+        for (width, height) in [(160, 90), (90, 160), (16, 9), (1000, 1)] {
+            let image = DynamicImage::ImageRgba8(image::RgbaImage::new(width, height));
+            let thumbnail = image.thumbnail(128, 128);
+            assert_eq!(
+                thumbnail_size(width, height, 128),
+                (thumbnail.width(), thumbnail.height())
+            );
+        }
+    }
+
+    #[test]
+    fn test_small_palette() {
+        let config = PaletteConfig {
+            palette_size: 4,
+            downscale: 32,
+            ..Default::default()
+        };
+        let (primary, accent) = extract_colors_with(&dummy_image(), &config).unwrap();
+        assert_ne!(primary, accent);
+        assert_eq!(primary.a, 255);
+        assert_eq!(accent.a, 255);
+    }
+
+    #[test]
+    fn test_ranked_colors() {
+        let image = dummy_image();
+        let ranked = extract_ranked_colors(&image, 1).unwrap();
+        assert_eq!(ranked, vec![HexColor::rgb(2, 60, 136)]);
+        // Only two hues are present, the gray noise is filtered out
+        let ranked = extract_ranked_colors(&image, 16).unwrap();
+        assert_eq!(
+            ranked,
+            vec![HexColor::rgb(2, 60, 136), HexColor::rgb(240, 120, 16)]
+        );
+        assert!(ranked.iter().all(|c| c.a == 255));
+        assert_eq!(extract_colors(&image).unwrap(), (ranked[0], ranked[1]));
+    }
+
+    #[test]
+    fn test_solid_color() {
+        // A single swatch, scoring keeps at most one color
+        let blue = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([2, 60, 136])));
+        let ranked = extract_ranked_colors(&blue, 8).unwrap();
+        assert_eq!(ranked, vec![HexColor::rgb(2, 60, 136)]);
+        let (primary, accent) = extract_colors(&blue).unwrap();
+        assert_eq!(primary, HexColor::rgb(2, 60, 136));
+        // Dark colors get a lighter accent
+        assert_eq!(accent, HexColor::rgb(103, 138, 184));
+
+        // Gray is filtered out by scoring entirely
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([128, 128, 128])));
+        let (primary, accent) = extract_colors(&gray).unwrap();
+        assert_eq!(primary, HexColor::rgb(128, 128, 128));
+        assert_eq!(accent, HexColor::rgb(77, 77, 77));
+
+        for pixel in [[0, 0, 0], [255, 255, 255], [240, 120, 16]] {
+            let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb(pixel)));
+            let (primary, accent) = extract_colors(&image).unwrap();
+            assert_eq!(primary, HexColor::rgb(pixel[0], pixel[1], pixel[2]));
+            assert_ne!(primary, accent);
+            assert_eq!(accent.a, 255);
+        }
+    }
+
+    #[test]
+    fn test_contrast_ratio() {
+        let black = HexColor::rgb(0, 0, 0);
+        let white = HexColor::rgb(255, 255, 255);
+        assert!((contrast_ratio(black, white) - 21.0).abs() < 1e-9);
+        assert!((contrast_ratio(white, black) - 21.0).abs() < 1e-9);
+        assert_eq!(contrast_ratio(white, white), 1.0);
+        let navy = HexColor::rgb(2, 60, 136);
+        assert_eq!(contrast_ratio(navy, navy), 1.0);
+    }
+}
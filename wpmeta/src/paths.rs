@@ -0,0 +1,35 @@
+//! Install locations of the generated files, relative to the install
+//! prefix.
+
+use std::path::{Component, Path};
+
+use crate::generate::Target;
+
+pub static DEFAULT_PREFIX: &str = "usr/share";
+
+pub static MATE_META_BASE: &str = "mate-background-properties";
+pub static GNOME_META_BASE: &str = "gnome-background-properties";
+pub static CINNAMON_META_BASE: &str = "cinnamon-background-properties";
+pub static BUDGIE_META_BASE: &str = "budgie-background-properties";
+pub static KDE_META_BASE: &str = "wallpapers";
+pub static HYPRLAND_META_BASE: &str = "wpmeta/hyprland";
+pub static SWAY_META_BASE: &str = "wpmeta/sway";
+
+/// Directory the manifests of `target` are written to.
+pub fn meta_base(target: Target) -> &'static str {
+    match target {
+        Target::Kde => KDE_META_BASE,
+        Target::Gnome => GNOME_META_BASE,
+        Target::Mate => MATE_META_BASE,
+        Target::Cinnamon => CINNAMON_META_BASE,
+        Target::Budgie => BUDGIE_META_BASE,
+        Target::Hyprland => HYPRLAND_META_BASE,
+        Target::Sway => SWAY_META_BASE,
+    }
+}
+
+/// Whether `path` is relative and made only of plain names, so joining it
+/// to a directory stays inside.
+pub fn is_contained(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
@@ -0,0 +1,1006 @@
+//! The generation pipeline, usable without the command line.
+
+use clap::{ArgAction, Parser, ValueEnum};
+use eyre::{bail, eyre, Result, WrapErr};
+use image::DynamicImage;
+use indicatif::ProgressBar;
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, copy, create_dir_all, hard_link, remove_dir_all, remove_file, File};
+use std::io::{self, Write};
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::WpmetaError;
+use crate::generate::{
+    generators_for, render_kde_collection, render_manifests, CatalogEntry, GenerationReport,
+    Generator, Manifest, ManifestContent, RenderContext, Target, WallpaperCatalog, WallpaperReport,
+};
+use crate::meta::{Metadata, Resolution, Wallpaper};
+use crate::palette::PaletteConfig;
+use crate::paths::{self, DEFAULT_PREFIX, KDE_META_BASE};
+use crate::preview::{self, CropFocus, ExtraPreview, PreviewFormat, PreviewMode, PreviewSource};
+use crate::{icc, orientation, progress, stamp, walk};
+
+/// Options of a generation run.
+#[derive(clap::Args)]
+pub struct GenerateOptions {
+    /// Install prefix under the destination, e.g. usr/local/share
+    #[arg(long, default_value = DEFAULT_PREFIX)]
+    pub prefix: PathBuf,
+    /// Directory the destination gets installed to, used for the absolute
+    /// paths written inside manifests
+    #[arg(long, default_value = "/")]
+    pub install_root: PathBuf,
+    /// Indentation of JSON manifests in spaces, 0 for a single line
+    #[arg(long, default_value_t = 2)]
+    pub output_manifest_indent: usize,
+    /// Desktop environments to generate metadata for
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = Target::all())]
+    pub targets: Vec<Target>,
+    /// Hardlink wallpaper files with identical content instead of copying them again
+    #[arg(long)]
+    pub dedup: bool,
+    /// Check that copied wallpaper files match their source
+    #[arg(long)]
+    pub verify_copies: bool,
+    /// Symlink wallpaper files to their source instead of copying them when
+    /// both are on the same filesystem
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "relative")]
+    pub symlink_images: Option<LinkStyle>,
+    /// Resolve symlinked source images to the file they point at, so
+    /// links from --symlink-images skip them. Copies always read the image
+    /// itself
+    #[arg(long, default_value_t = true, action = ArgAction::Set, value_name = "BOOL")]
+    pub deref_sources: bool,
+    /// Fail on malformed locale tags in translations
+    #[arg(long)]
+    pub strict_locales: bool,
+    /// Number of wallpapers processed in parallel, 0 for one per CPU
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+    /// Show a progress bar when stderr is a terminal
+    #[arg(long)]
+    pub progress: bool,
+    /// Remove previously generated outputs before generating
+    #[arg(long)]
+    pub clean: bool,
+    /// Fail instead of warning when no metadata is found under the source
+    #[arg(long)]
+    pub require_wallpapers: bool,
+    /// Fail on licenses that are not valid SPDX expressions
+    #[arg(long)]
+    pub strict_license: bool,
+    /// Extract colors wallpapers don't specify from their image
+    #[arg(long)]
+    pub extract_colors: bool,
+    /// Add BlurHash placeholders to the KDE metadata
+    #[arg(long)]
+    pub blurhash: bool,
+    /// Declare the Plasma package structure in the KDE metadata
+    #[arg(long)]
+    pub kde_package_structure: bool,
+    /// Fail when the primary and secondary colors have low contrast
+    #[arg(long)]
+    pub enforce_contrast: bool,
+    /// Warn when a dark variant is not darker than its normal image
+    #[arg(long)]
+    pub check_dark_variants: bool,
+    /// Fail when a dark variant is not darker than its normal image
+    #[arg(long)]
+    pub strict_dark_variants: bool,
+    /// Reject images smaller than this resolution, as WIDTHxHEIGHT
+    #[arg(long)]
+    pub min_resolution: Option<Resolution>,
+    /// Bounding box of generated previews, as WIDTHxHEIGHT
+    #[arg(long, default_value = "500,500")]
+    pub preview_resolution_limit: Resolution,
+    /// Another preview to write next to the screenshot, e.g.
+    /// thumbnail=256x256, may be repeated
+    #[arg(long = "extra-preview", value_name = "NAME=WIDTHxHEIGHT")]
+    pub extra_previews: Vec<ExtraPreview>,
+    /// Image format of generated previews
+    #[arg(long, value_enum, default_value_t)]
+    pub preview_format: PreviewFormat,
+    /// How the preview is fitted into its bounding box
+    #[arg(long, value_enum, default_value_t)]
+    pub preview_mode: PreviewMode,
+    /// Where the crop window is anchored in cover mode
+    #[arg(long, value_enum, default_value_t)]
+    pub preview_crop_focus: CropFocus,
+    /// Which variant previews are generated from
+    #[arg(long, value_enum, default_value_t)]
+    pub preview_source: PreviewSource,
+    /// What to do with files already in the destination
+    #[arg(long, value_enum, default_value_t)]
+    pub overwrite_policy: OverwritePolicy,
+    /// Rewrite manifests even if their content is unchanged
+    #[arg(long)]
+    pub force: bool,
+    /// Note the wpmeta version and the time of the run in GNOME and KDE
+    /// manifests, SOURCE_DATE_EPOCH is used as the time if set
+    #[arg(long)]
+    pub stamp: bool,
+    /// Generate the same bytes from the same source on every run, leaving
+    /// out stamps even if asked for
+    #[arg(long)]
+    pub reproducible: bool,
+    /// Only regenerate previews, leaving manifests and copied images of a
+    /// previous run as they are
+    #[arg(long, conflicts_with = "clean")]
+    pub preview_only: bool,
+    /// Only generate the wallpaper with this id, may be repeated
+    #[arg(long = "only-id", value_name = "ID")]
+    pub only_ids: Vec<String>,
+    /// Skip the wallpaper with this id, may be repeated
+    #[arg(long = "exclude-id", value_name = "ID")]
+    pub exclude_ids: Vec<String>,
+}
+
+impl Default for GenerateOptions {
+    /// The defaults of the command line.
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            options: GenerateOptions,
+        }
+        Defaults::parse_from(["wpmeta"]).options
+    }
+}
+
+/// What a generation run produced besides the files in the destination.
+#[derive(Debug)]
+pub struct Generation {
+    pub report: GenerationReport,
+    pub catalog: WallpaperCatalog,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OverwritePolicy {
+    /// Replace existing files
+    #[default]
+    Overwrite,
+    /// Keep existing files
+    Skip,
+    /// Fail on existing files
+    Error,
+}
+
+/// How symlinks to source images are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LinkStyle {
+    /// Relative to the link, so the trees can be moved together
+    Relative,
+    /// Absolute path of the source
+    Absolute,
+}
+
+impl OverwritePolicy {
+    /// Returns whether `target` should be written, failing if it exists and
+    /// the policy forbids replacing it.
+    fn allows(self, target: &Path) -> Result<bool> {
+        if target.symlink_metadata().is_err() {
+            return Ok(true);
+        }
+        match self {
+            Self::Overwrite => Ok(true),
+            Self::Skip => {
+                debug!("keeping existing {}", target.display());
+                Ok(false)
+            }
+            Self::Error => bail!("{} already exists", target.display()),
+        }
+    }
+}
+
+fn ensure_dir(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        debug!("creating directory at {}", dir.display());
+        create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+fn ensure_parent(file: &Path) -> Result<()> {
+    if let Some(parent) = file.parent() {
+        ensure_dir(parent)
+    } else {
+        bail!("invalid path");
+    }
+}
+
+/// Writes `content` to `target`. Files already holding `content` are left
+/// alone unless `force` is set, so their mtime doesn't change.
+pub fn write_file(
+    target: &Path,
+    content: &[u8],
+    policy: OverwritePolicy,
+    force: bool,
+) -> Result<()> {
+    // A link left by a previous run points outside the destination
+    let is_link = target.symlink_metadata().is_ok_and(|m| m.is_symlink());
+    if !force && !is_link && fs::read(target).is_ok_and(|existing| existing == content) {
+        debug!("{} is unchanged", target.display());
+        return Ok(());
+    }
+    if !policy.allows(target)? {
+        return Ok(());
+    }
+    if is_link {
+        remove_file(target)?;
+    }
+    ensure_parent(target)?;
+    debug!("writing to {}", target.display());
+    let mut f = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(target)?;
+    f.write_all(content)?;
+    Ok(())
+}
+
+/// Writes a preview of `src` at each target, fitted into its limit. The
+/// image is only loaded if some target gets written.
+fn generate_previews(
+    src: &Path,
+    load: impl FnOnce() -> Result<Arc<DynamicImage>, WpmetaError>,
+    targets: &[(PathBuf, Resolution)],
+    opts: &GenerateOptions,
+) -> Result<()> {
+    let mut load = Some(load);
+    let mut img = None;
+    let mut profile = None;
+    for (target, limit) in targets {
+        if !opts.overwrite_policy.allows(target)? {
+            continue;
+        }
+        let img = match &img {
+            Some(img) => img,
+            None => {
+                // Previews would otherwise be shown in sRGB
+                profile = icc::read(src);
+                img.insert(load.take().expect("Images are loaded once")()?)
+            }
+        };
+        if img.width() < limit.width && img.height() < limit.height {
+            // Previews are never upscaled
+            warn!(
+                "{}: image is {}x{}, preview will be smaller than {}x{}",
+                src.display(),
+                img.width(),
+                img.height(),
+                limit.width,
+                limit.height
+            );
+        }
+        let preview = preview::resize(img, *limit, opts.preview_mode, opts.preview_crop_focus);
+        ensure_parent(target)?;
+        opts.preview_format
+            .save(&preview, target, profile.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Writes a rendered manifest under `dst`.
+fn write_manifest(
+    dst: &Path,
+    manifest: &Manifest,
+    policy: OverwritePolicy,
+    force: bool,
+) -> Result<()> {
+    let path = dst.join(&manifest.path);
+    match &manifest.content {
+        ManifestContent::Text(content) => write_file(&path, content.as_bytes(), policy, force),
+        ManifestContent::Link(target) => {
+            if !force && path.read_link().is_ok_and(|existing| &existing == target) {
+                debug!("{} is unchanged", path.display());
+                return Ok(());
+            }
+            if !policy.allows(&path)? {
+                return Ok(());
+            }
+            if path.symlink_metadata().is_ok() {
+                remove_file(&path)?;
+            }
+            ensure_parent(&path)?;
+            symlink(target, &path)?;
+            Ok(())
+        }
+    }
+}
+
+/// Hashes the content of the file at `path`.
+fn hash_file(path: &Path) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Content hashes of files already copied into the destination tree.
+#[derive(Debug, Default)]
+struct CopiedFiles(Mutex<HashMap<[u8; 32], PathBuf>>);
+
+impl CopiedFiles {
+    /// Returns an existing copy of `src`, or records `dst` as its copy.
+    fn existing_or_insert(&self, src: &Path, dst: &Path) -> Result<Option<PathBuf>> {
+        let hash = hash_file(src)?;
+        let mut copied = self.0.lock().expect("Copied files lock poisoned");
+        match copied.get(&hash) {
+            Some(existing) if existing != dst => Ok(Some(existing.clone())),
+            Some(_) => Ok(None),
+            None => {
+                copied.insert(hash, dst.to_owned());
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Symlinks `dst` to `src`, or to what it points at with `deref`, returning
+/// false without touching anything if they are on different filesystems.
+fn link_image(src: &Path, dst: &Path, style: LinkStyle, deref: bool) -> Result<bool> {
+    let src = match (deref, src.parent(), src.file_name()) {
+        // Only the directories are resolved, keeping the link itself
+        (false, Some(parent), Some(name)) => match parent.as_os_str().is_empty() {
+            true => env::current_dir()?.join(name),
+            false => parent.canonicalize()?.join(name),
+        },
+        _ => src.canonicalize()?,
+    };
+    let dir = dst
+        .parent()
+        .ok_or_else(|| eyre!("invalid destination {}", dst.display()))?
+        .canonicalize()?;
+    if fs::metadata(&src)?.dev() != fs::metadata(&dir)?.dev() {
+        debug!(
+            "{} is on another filesystem, copying instead",
+            src.display()
+        );
+        return Ok(false);
+    }
+    let target = match style {
+        LinkStyle::Relative => relative_path(&dir, &src),
+        LinkStyle::Absolute => src,
+    };
+    if dst.read_link().is_ok_and(|existing| existing == target) {
+        return Ok(true);
+    }
+    if dst.symlink_metadata().is_ok() {
+        remove_file(dst)?;
+    }
+    debug!("linking {} to {}", dst.display(), target.display());
+    symlink(target, dst)?;
+    Ok(true)
+}
+
+/// Path of `target` relative to `dir`, both absolute.
+fn relative_path(dir: &Path, target: &Path) -> PathBuf {
+    let dir: Vec<_> = dir.components().collect();
+    let target: Vec<_> = target.components().collect();
+    let common = dir.iter().zip(&target).take_while(|(a, b)| a == b).count();
+    let mut ret: PathBuf = dir[common..].iter().map(|_| "..").collect();
+    ret.extend(&target[common..]);
+    ret
+}
+
+/// Copies `src` to `dst`, links an existing copy with `dedup`, or symlinks
+/// the source with `--symlink-images`. With
+/// `--verify-copies`, a copy whose content differs from `src`, e.g. after a
+/// truncated write, is removed and fails the run.
+fn copy_file(
+    src: &Path,
+    dst: &Path,
+    dedup: Option<&CopiedFiles>,
+    opts: &GenerateOptions,
+) -> Result<()> {
+    if !src.is_file() {
+        bail!("src {} is not a file", src.display());
+    }
+    if !opts.overwrite_policy.allows(dst)? {
+        return Ok(());
+    }
+    if let Some(parent) = dst.parent() {
+        ensure_dir(parent)?;
+    } else {
+        bail!("invalid destination {}", dst.display());
+    }
+    if let Some(style) = opts.symlink_images {
+        if link_image(src, dst, style, opts.deref_sources)? {
+            return Ok(());
+        }
+    }
+    // Copying through a symlink or a hardlink left by a previous run would
+    // clobber its other end
+    if dst.symlink_metadata().is_ok() {
+        remove_file(dst)?;
+    }
+    let existing = dedup
+        .map(|d| d.existing_or_insert(src, dst))
+        .transpose()?
+        .flatten();
+    if let Some(existing) = existing {
+        debug!("linking {} to {}", existing.display(), dst.display());
+        match hard_link(&existing, dst) {
+            Ok(()) => return Ok(()),
+            // Most likely on another filesystem
+            Err(e) => debug!("hardlink failed ({}), copying instead", e),
+        }
+    }
+    debug!("copying {} to {}", src.display(), dst.display());
+    copy(src, dst)?;
+    if opts.verify_copies && hash_file(src)? != hash_file(dst)? {
+        remove_file(dst)?;
+        bail!(
+            "copy of {} to {} does not match the source",
+            src.display(),
+            dst.display()
+        );
+    }
+    Ok(())
+}
+
+/// Writes the previews of `wallpaper` for KDE, returning the paths of the
+/// screenshot and of the extra previews relative to `dst`.
+fn write_previews(
+    wallpaper: &Wallpaper,
+    base: &Path,
+    dst: &Path,
+    opts: &GenerateOptions,
+) -> Result<Option<(PathBuf, Vec<PathBuf>)>> {
+    // Sources are picked for the largest preview
+    let preview_limit = opts
+        .extra_previews
+        .iter()
+        .map(|p| p.limit)
+        .chain([opts.preview_resolution_limit])
+        .max_by_key(|r| u64::from(r.width) * u64::from(r.height))
+        .unwrap_or(opts.preview_resolution_limit);
+    let preview_src = match (opts.preview_source, wallpaper.dark_src()) {
+        // Plasma picks between the variants itself
+        (PreviewSource::Auto, Some(_)) => return Ok(None),
+        (PreviewSource::Dark, Some(dark)) => (dark.to_owned(), None),
+        _ => {
+            let file = wallpaper.best_source_for(base, preview_limit)?;
+            (file.src().to_owned(), Some(file))
+        }
+    };
+    info!("{}: generating preview ...", wallpaper.id());
+    let contents = opts.prefix.join(wallpaper.package_dir()).join("contents");
+    let extension = opts.preview_format.extension();
+    let path = contents.join("screenshot").with_extension(extension);
+    let extras: Vec<_> = opts
+        .extra_previews
+        .iter()
+        .map(|p| (contents.join(&p.name).with_extension(extension), p.limit))
+        .collect();
+    let targets: Vec<_> = [(path.clone(), opts.preview_resolution_limit)]
+        .into_iter()
+        .chain(extras.iter().cloned())
+        .map(|(path, limit)| (dst.join(path), limit))
+        .collect();
+    let src = base.join(preview_src.0);
+    let load = || match preview_src.1 {
+        Some(file) => file.image(base),
+        // Dark variants are only ever decoded here
+        None => orientation::open(&src).map(Arc::new),
+    };
+    generate_previews(&src, load, &targets, opts)?;
+    Ok(Some((
+        path,
+        extras.into_iter().map(|(path, _)| path).collect(),
+    )))
+}
+
+/// Shared state accumulated across the parallel generation tasks.
+#[derive(Debug, Default)]
+struct Outputs {
+    report: GenerationReport,
+    catalog: WallpaperCatalog,
+    copied: CopiedFiles,
+    ids: walk::WallpaperIds,
+}
+
+fn process_meta(
+    mut meta: Metadata,
+    dst: &Path,
+    opts: &GenerateOptions,
+    generators: &[Box<dyn Generator>],
+    stamp: Option<&str>,
+    outputs: &Outputs,
+    progress: &ProgressBar,
+) -> Result<()> {
+    info!("processing meta at {:?}", meta.base());
+    // Ids are checked across the whole tree, filtered out or not
+    outputs.ids.insert(&meta)?;
+    let targets = &opts.targets;
+    // The collection keeps listing every member, including those filtered
+    // out of this run
+    let index = if targets.contains(&Target::Kde) && !opts.preview_only {
+        render_kde_collection(&meta, opts.output_manifest_indent)?
+    } else {
+        None
+    };
+    if !opts.only_ids.is_empty() || !opts.exclude_ids.is_empty() {
+        meta.retain_wallpapers(|w| {
+            let listed = |ids: &[String]| ids.iter().any(|id| id == w.id());
+            (opts.only_ids.is_empty() || listed(&opts.only_ids)) && !listed(&opts.exclude_ids)
+        });
+        if meta.wallpapers().is_some_and(Vec::is_empty) {
+            return Ok(());
+        }
+    }
+    meta.check_locales(opts.strict_locales)?;
+    let prefix = &opts.prefix;
+    // Where the prefix ends up on the installed system
+    let installed = opts.install_root.join(prefix);
+    let base = meta
+        .base()
+        .map_or_else(|| PathBuf::from("."), Path::to_owned);
+    let base = base.as_path();
+    // Everything working on pixels shares one decode of each source, which
+    // is dropped before moving on to the next wallpaper
+    let mut previews = HashMap::new();
+    for wallpaper in meta.wallpapers_mut() {
+        wallpaper.tag_aspect_ratio(base)?;
+        // Colors only end up in manifests
+        if opts.extract_colors && !opts.preview_only {
+            wallpaper.extract_colors(base, &PaletteConfig::default())?;
+        }
+        if opts.blurhash && targets.contains(&Target::Kde) && !opts.preview_only {
+            wallpaper.compute_blurhash(base)?;
+        }
+        if opts.check_dark_variants || opts.strict_dark_variants {
+            wallpaper.check_dark_variant(base, opts.strict_dark_variants)?;
+        }
+        if targets.contains(&Target::Kde) {
+            if let Some(preview) = write_previews(wallpaper, base, dst, opts)? {
+                previews.insert(wallpaper.id().to_owned(), preview);
+            }
+        }
+        wallpaper.release_images();
+    }
+    if opts.preview_only {
+        for wallpaper in meta.wallpapers().into_iter().flatten() {
+            let mut wallpaper_report = WallpaperReport::new(wallpaper, base, prefix)?;
+            if let Some((preview, extras)) = previews.remove(wallpaper.id()) {
+                wallpaper_report.preview = Some(preview);
+                wallpaper_report.extra_previews = extras;
+            }
+            outputs.report.insert(wallpaper.id(), wallpaper_report);
+        }
+        return Ok(());
+    }
+    let ctx = RenderContext {
+        base,
+        prefix,
+        installed: &installed,
+        targets,
+        indent: opts.output_manifest_indent,
+        kde_package_structure: opts.kde_package_structure,
+        stamp,
+    };
+    let mut manifests = render_manifests(&meta, generators, &ctx)?;
+    if let (Some(index), Some(collection)) = (index, meta.collection()) {
+        let id = collection.id();
+        info!("{}: writing collection index", id);
+        write_file(
+            &dst.join(prefix)
+                .join(KDE_META_BASE)
+                .join(id)
+                .join("collection.json"),
+            index.as_bytes(),
+            opts.overwrite_policy,
+            opts.force,
+        )?;
+    }
+    let wallpapers = meta
+        .wallpapers()
+        .ok_or_else(|| eyre!("no wallpapers in {}", base.display()))?;
+    progress.inc_length(wallpapers.len() as u64);
+    for wallpaper in wallpapers {
+        wallpaper.check_license(opts.strict_license)?;
+        wallpaper.check_contrast(opts.enforce_contrast)?;
+        if let Some(min) = opts.min_resolution {
+            wallpaper.check_resolution(base, min)?;
+        }
+        let id = wallpaper.id();
+        let src = base.join(wallpaper.src());
+        let target = wallpaper.target(base, prefix)?;
+        let mut wallpaper_report = WallpaperReport::new(wallpaper, base, prefix)?;
+
+        info!("{}: writing metadata", id);
+        for manifest in manifests.remove(id).unwrap_or_default() {
+            write_manifest(dst, &manifest, opts.overwrite_policy, opts.force)?;
+            wallpaper_report.manifests.push(manifest.path);
+        }
+
+        let wallpaper_dst = dst.join(target);
+        info!(
+            "{}: copying wallpaper file {} -> {}",
+            id,
+            src.display(),
+            wallpaper_dst.display()
+        );
+        let dedup = opts.dedup.then_some(&outputs.copied);
+        copy_file(&src, &wallpaper_dst, dedup, opts)?;
+        if let (Some(dark_src), Some(dark_target)) =
+            (wallpaper.dark_src(), wallpaper.dark_target(base, prefix)?)
+        {
+            info!("{}: copying dark variant {}", id, dark_src.display());
+            copy_file(&base.join(dark_src), &dst.join(dark_target), dedup, opts)?;
+        }
+        if let Some(text) = wallpaper.license_text() {
+            let path = dst
+                .join(prefix)
+                .join(wallpaper.package_dir())
+                .join("LICENSE.txt");
+            info!("{}: writing license", id);
+            write_file(&path, text.as_bytes(), opts.overwrite_policy, opts.force)?;
+        }
+        // KDE picks among the resolutions in the images directory by name
+        for file in wallpaper.sources().skip(1) {
+            let target = prefix.join(file.get_meta(base)?.target());
+            info!("{}: copying resolution {}", id, file.src().display());
+            copy_file(&base.join(file.src()), &dst.join(target), dedup, opts)?;
+        }
+
+        if let Some((preview, extras)) = previews.remove(id) {
+            wallpaper_report.preview = Some(preview);
+            wallpaper_report.extra_previews = extras;
+        }
+        outputs.catalog.push(CatalogEntry::new(
+            &meta,
+            wallpaper,
+            base,
+            prefix,
+            wallpaper_report.preview.clone(),
+        )?);
+        outputs.report.insert(id, wallpaper_report);
+        progress.inc(1);
+    }
+    Ok(())
+}
+
+/// Removes the directories under `dst` written for `targets`. Wallpaper
+/// images are always copied into the KDE directory, so it is removed too.
+pub fn clean_outputs(dst: &Path, prefix: &Path, targets: &[Target]) -> Result<()> {
+    let bases = targets
+        .iter()
+        .map(|t| paths::meta_base(*t))
+        .chain([KDE_META_BASE]);
+    for base in bases {
+        let dir = dst.join(prefix).join(base);
+        if dir.symlink_metadata().is_ok() {
+            info!("removing {}", dir.display());
+            remove_dir_all(&dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Generates metadata for every wallpaper found under `src` into `dst`.
+pub fn generate_all(src: &Path, dst: &Path, opts: &GenerateOptions) -> Result<Generation> {
+    if !opts.install_root.is_absolute() {
+        bail!(
+            "install root {} is not an absolute path",
+            opts.install_root.display()
+        );
+    }
+    // Extra previews of the same name would overwrite each other
+    for (i, extra) in opts.extra_previews.iter().enumerate() {
+        if let Some(other) = opts.extra_previews[..i]
+            .iter()
+            .find(|p| p.name == extra.name)
+        {
+            bail!(
+                "extra previews {} and {} are both named {:?}",
+                other.limit,
+                extra.limit,
+                extra.name
+            );
+        }
+    }
+    // Everything is written, and with --clean removed, under dst/prefix
+    if !paths::is_contained(&opts.prefix) {
+        return Err(WpmetaError::InvalidPrefix(opts.prefix.clone()).into());
+    }
+    let metas = walk::Walk::new(src, None)?;
+    if opts.clean {
+        clean_outputs(dst, &opts.prefix, &opts.targets)?;
+    }
+
+    let outputs = Outputs::default();
+    let generators = generators_for(&opts.targets);
+    // Taken once, so every manifest of the run has the same
+    let stamp = (opts.stamp && !opts.reproducible).then(stamp::stamp);
+    let progress = progress::bar(opts.progress);
+    // rayon takes 0 threads as its default
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opts.jobs)
+        .build()?;
+    // Manifests are processed as they are found
+    let found = AtomicUsize::new(0);
+    pool.install(|| {
+        metas.par_bridge().try_for_each(|m| {
+            found.fetch_add(1, Ordering::Relaxed);
+            process_meta(
+                m?,
+                dst,
+                opts,
+                &generators,
+                stamp.as_deref(),
+                &outputs,
+                &progress,
+            )
+            .wrap_err("failed to process wallpapers")
+        })
+    })?;
+    progress.finish_and_clear();
+    if let Some(id) = opts.only_ids.iter().find(|id| !outputs.ids.contains(id)) {
+        bail!("no wallpaper with id {:?} under {}", id, src.display());
+    }
+    // Most likely a wrong --src
+    if found.into_inner() == 0 {
+        if opts.require_wallpapers {
+            return Err(WpmetaError::NoMetadata(src.to_owned()).into());
+        }
+        warn!("no metadata.toml found under {}", src.display());
+    }
+    Ok(Generation {
+        report: outputs.report,
+        catalog: outputs.catalog,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{copy_file, generate_all, write_manifest, GenerateOptions, OverwritePolicy};
+    use crate::error::WpmetaError;
+    use crate::generate::{Manifest, ManifestContent, Target};
+    use crate::paths::{DEFAULT_PREFIX, GNOME_META_BASE, KDE_META_BASE, MATE_META_BASE};
+    use crate::test::{dummy_tree, write_dummy_dir, DUMMY_TREE_META};
+
+    #[test]
+    fn test_verified_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("kusa.png");
+        fs::write(&src, b"not really a png").unwrap();
+        let opts = GenerateOptions {
+            verify_copies: true,
+            ..Default::default()
+        };
+        let dst = dir.path().join("out/kusa.png");
+        copy_file(&src, &dst, None, &opts).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), b"not really a png");
+    }
+
+    #[test]
+    fn test_filter_ids() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        write_dummy_dir(
+            &src.path().join("hana"),
+            &DUMMY_TREE_META.replace("Kusa", "Hana"),
+        );
+        let generated = |only: &[&str], exclude: &[&str]| {
+            let dst = tempfile::tempdir().unwrap();
+            let opts = GenerateOptions {
+                only_ids: only.iter().map(|id| id.to_string()).collect(),
+                exclude_ids: exclude.iter().map(|id| id.to_string()).collect(),
+                ..Default::default()
+            };
+            generate_all(src.path(), dst.path(), &opts)?;
+            let kde = dst.path().join(DEFAULT_PREFIX).join(KDE_META_BASE);
+            Ok::<_, eyre::Report>(["Hana", "Kusa"].map(|id| kde.join(id).exists()))
+        };
+        assert_eq!(generated(&[], &[]).unwrap(), [true, true]);
+        assert_eq!(generated(&["Kusa"], &[]).unwrap(), [false, true]);
+        assert_eq!(generated(&[], &["Kusa"]).unwrap(), [true, false]);
+        assert_eq!(generated(&["Kusa"], &["Kusa"]).unwrap(), [false, false]);
+        assert!(generated(&["Kusa", "Hoshi"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_single_decode() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let opts = GenerateOptions {
+            extract_colors: true,
+            blurhash: true,
+            ..Default::default()
+        };
+        generate_all(src.path(), dst.path(), &opts).unwrap();
+        let kde = dst.path().join(DEFAULT_PREFIX).join(KDE_META_BASE);
+        assert!(kde.join("Kusa/contents/screenshot.jpg").is_file());
+        // Previews, the palette and the BlurHash share a single decode
+        let decoded = crate::orientation::test::DECODED.lock().unwrap();
+        let kusa = src.path().join("kusa.png");
+        assert_eq!(decoded.iter().filter(|path| **path == kusa).count(), 1);
+    }
+
+    #[test]
+    fn test_filtered_collection() {
+        let src = dummy_tree(
+            r#"
+            [collection]
+            id = "Plants"
+            name.default = "Plants"
+
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.default = "Yajuu Senpai"
+
+            [[wallpapers]]
+            title.default = "Kusa"
+            license = "CC BY-SA 4.0"
+            id = "Kusa"
+            path = "kusa.png"
+
+            [[wallpapers]]
+            title.default = "Hana"
+            license = "CC BY-SA 4.0"
+            id = "Hana"
+            path = "hana.png"
+            "#,
+        );
+        let dst = tempfile::tempdir().unwrap();
+        let opts = GenerateOptions {
+            exclude_ids: vec!["Hana".to_string()],
+            ..Default::default()
+        };
+        generate_all(src.path(), dst.path(), &opts).unwrap();
+        let kde = dst.path().join(DEFAULT_PREFIX).join(KDE_META_BASE);
+        assert!(!kde.join("Hana").exists());
+        let index = fs::read_to_string(kde.join("Plants/collection.json")).unwrap();
+        assert!(index.contains("\"Kusa\""));
+        assert!(index.contains("\"Hana\""));
+    }
+
+    #[test]
+    fn test_invalid_prefix() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let root = tempfile::tempdir().unwrap();
+        let dst = root.path().join("dst");
+        let outside = root.path().join("outside");
+        fs::create_dir_all(outside.join(KDE_META_BASE)).unwrap();
+        for prefix in [
+            outside.clone(),
+            "../outside".into(),
+            "usr/../../outside".into(),
+        ] {
+            for clean in [false, true] {
+                let opts = GenerateOptions {
+                    prefix: prefix.clone(),
+                    clean,
+                    ..Default::default()
+                };
+                let err = generate_all(src.path(), &dst, &opts).unwrap_err();
+                assert!(matches!(
+                    err.downcast_ref::<WpmetaError>(),
+                    Some(WpmetaError::InvalidPrefix(_))
+                ));
+            }
+        }
+        assert!(outside.join(KDE_META_BASE).is_dir());
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_stale_manifest_link() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let installed = root
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(GNOME_META_BASE)
+            .join("Kusa.xml");
+        fs::create_dir_all(installed.parent().unwrap()).unwrap();
+        fs::write(&installed, "installed").unwrap();
+        let generate = |targets: Vec<Target>| {
+            let opts = GenerateOptions {
+                targets,
+                install_root: root.path().to_owned(),
+                ..Default::default()
+            };
+            generate_all(src.path(), dst.path(), &opts).unwrap();
+        };
+        let mate = dst
+            .path()
+            .join(DEFAULT_PREFIX)
+            .join(MATE_META_BASE)
+            .join("Kusa.xml");
+        generate(vec![Target::Gnome, Target::Mate]);
+        assert_eq!(mate.read_link().unwrap(), installed);
+        // Without GNOME the MATE manifest is written in place of the link
+        generate(vec![Target::Mate]);
+        assert!(mate.symlink_metadata().unwrap().is_file());
+        assert!(fs::read_to_string(&mate).unwrap().contains("Kusa"));
+        assert_eq!(fs::read_to_string(&installed).unwrap(), "installed");
+    }
+
+    #[test]
+    fn test_duplicate_extra_previews() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        let dst = tempfile::tempdir().unwrap();
+        let opts = GenerateOptions {
+            extra_previews: vec![
+                "thumbnail=16x16".parse().unwrap(),
+                "thumbnail=32x32".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let err = generate_all(src.path(), dst.path(), &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "extra previews 16x16 and 32x32 are both named \"thumbnail\""
+        );
+        assert!(!dst.path().join(DEFAULT_PREFIX).exists());
+    }
+
+    #[test]
+    fn test_missing_image() {
+        let src = dummy_tree(DUMMY_TREE_META);
+        fs::remove_file(src.path().join("kusa.png")).unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let err = generate_all(src.path(), dst.path(), &GenerateOptions::default()).unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<WpmetaError>(),
+                Some(WpmetaError::MissingFile(path)) if path.ends_with("kusa.png")
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_no_metadata() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let generation = generate_all(src.path(), dst.path(), &GenerateOptions::default()).unwrap();
+        assert_eq!(generation.report.to_json().unwrap(), "{}");
+        let opts = GenerateOptions {
+            require_wallpapers: true,
+            ..Default::default()
+        };
+        let err = generate_all(src.path(), dst.path(), &opts).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<WpmetaError>(),
+            Some(WpmetaError::NoMetadata(path)) if path == src.path()
+        ));
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        let dst = tempfile::tempdir().unwrap();
+        let xml = "<wallpapers></wallpapers>";
+        let gnome = Path::new(GNOME_META_BASE).join("Kusa.xml");
+        let write = |path: &Path, content| {
+            let manifest = Manifest {
+                path: path.to_owned(),
+                content,
+            };
+            write_manifest(dst.path(), &manifest, OverwritePolicy::Overwrite, false).unwrap();
+        };
+        write(&gnome, ManifestContent::Text(xml.into()));
+        assert_eq!(fs::read_to_string(dst.path().join(&gnome)).unwrap(), xml);
+        // Links are replaced on later runs
+        let mate = Path::new(MATE_META_BASE).join("Kusa.xml");
+        for _ in 0..2 {
+            write(&mate, ManifestContent::Link(dst.path().join(&gnome)));
+        }
+        assert_eq!(fs::read_to_string(dst.path().join(mate)).unwrap(), xml);
+    }
+}
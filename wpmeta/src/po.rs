@@ -0,0 +1,184 @@
+use eyre::{bail, eyre, Result};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Catalog {
+    entries: HashMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    fuzzy: bool,
+    plural: bool,
+    msgid: Option<String>,
+    msgstr: Option<String>,
+}
+
+enum Field {
+    Ignored,
+    Id,
+    Str,
+}
+
+fn unquote(s: &str, lineno: usize) -> Result<String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| eyre!("line {}: expected a quoted string", lineno))?;
+    let mut ret = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('"') => ret.push('"'),
+            Some('\\') => ret.push('\\'),
+            Some(c) => bail!("line {}: unknown escape sequence \\{}", lineno, c),
+            None => bail!("line {}: unterminated escape sequence", lineno),
+        }
+    }
+    Ok(ret)
+}
+
+impl Entry {
+    fn commit(self, entries: &mut HashMap<String, String>) {
+        if self.fuzzy || self.plural {
+            return;
+        }
+        if let (Some(msgid), Some(msgstr)) = (self.msgid, self.msgstr) {
+            // The empty msgid holds the catalog header
+            if !msgid.is_empty() && !msgstr.is_empty() {
+                entries.insert(msgid, msgstr);
+            }
+        }
+    }
+}
+
+impl Catalog {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut entries = HashMap::new();
+        let mut entry = Entry::default();
+        let mut field = Field::Ignored;
+        for (lineno, line) in content.lines().enumerate() {
+            let lineno = lineno + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(flags) = line.strip_prefix("#,") {
+                if entry.msgid.is_some() {
+                    std::mem::take(&mut entry).commit(&mut entries);
+                }
+                entry.fuzzy |= flags.split(',').any(|f| f.trim() == "fuzzy");
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('"') {
+                let s = unquote(line, lineno)?;
+                match field {
+                    Field::Id => entry.msgid.get_or_insert_with(String::new).push_str(&s),
+                    Field::Str => entry.msgstr.get_or_insert_with(String::new).push_str(&s),
+                    Field::Ignored => (),
+                }
+                continue;
+            }
+            let (keyword, value) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| eyre!("line {}: expected a keyword and a value", lineno))?;
+            let value = unquote(value.trim(), lineno)?;
+            field = match keyword {
+                "msgctxt" => {
+                    if entry.msgid.is_some() {
+                        std::mem::take(&mut entry).commit(&mut entries);
+                    }
+                    Field::Ignored
+                }
+                "msgid" => {
+                    if entry.msgid.is_some() {
+                        std::mem::take(&mut entry).commit(&mut entries);
+                    }
+                    entry.msgid = Some(value);
+                    Field::Id
+                }
+                "msgstr" => {
+                    entry.msgstr = Some(value);
+                    Field::Str
+                }
+                "msgid_plural" => {
+                    entry.plural = true;
+                    Field::Ignored
+                }
+                k if k.starts_with("msgstr[") => Field::Ignored,
+                k => bail!("line {}: unknown keyword {}", lineno, k),
+            };
+        }
+        entry.commit(&mut entries);
+        Ok(Self { entries })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn get(&self, msgid: &str) -> Option<&str> {
+        self.entries.get(msgid).map(|s| s.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::Catalog;
+
+    pub static DUMMY_PO: &str = r#"
+# Chinese translations for wallpapers
+msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+msgid "Kusa"
+msgstr "草"
+
+#, fuzzy
+msgid "Yajuu Senpai"
+msgstr "野兽"
+
+msgid "Multi"
+"line"
+msgstr "多"
+"行"
+"#;
+
+    #[test]
+    fn test_parse() {
+        let catalog = Catalog::parse(DUMMY_PO).unwrap();
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog.get("Kusa"), Some("草"));
+        assert_eq!(catalog.get("Multiline"), Some("多行"));
+        assert_eq!(catalog.get("Yajuu Senpai"), None);
+        assert_eq!(catalog.get(""), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Catalog::parse("msgid Kusa").is_err());
+        assert!(Catalog::parse("msgfoo \"Kusa\"").is_err());
+    }
+}
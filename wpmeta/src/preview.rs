@@ -0,0 +1,276 @@
+use clap::ValueEnum;
+use eyre::{bail, eyre, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use log::debug;
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::icc;
+use crate::meta::Resolution;
+
+static BLURHASH_SIZE: u32 = 64;
+static BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+/// Pinned rather than left to the encoder, so previews stay the same
+/// across versions of `image`
+static JPEG_QUALITY: u8 = 75;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PreviewMode {
+    /// Scale the image down to fit within the preview, keeping all of it
+    #[default]
+    Fit,
+    /// Scale and crop the image to cover the whole preview
+    Cover,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CropFocus {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PreviewSource {
+    /// Preview the normal variant
+    #[default]
+    Normal,
+    /// Preview the dark variant if there is one
+    Dark,
+    /// Leave wallpapers with both variants without a preview
+    Auto,
+}
+
+/// A preview written next to the screenshot, e.g. a thumbnail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraPreview {
+    /// File name without the extension
+    pub name: String,
+    pub limit: Resolution,
+}
+
+impl FromStr for ExtraPreview {
+    type Err = eyre::Report;
+
+    /// Parses `NAME=WIDTHxHEIGHT`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, limit) = s
+            .split_once('=')
+            .ok_or_else(|| eyre!("expected NAME=WIDTHxHEIGHT, got {:?}", s))?;
+        if name.is_empty() || name.contains(['/', '.']) || name == "screenshot" {
+            bail!("invalid preview name {:?}", name);
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            limit: limit.parse()?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PreviewFormat {
+    #[default]
+    Jpeg,
+    #[value(name = "webp")]
+    WebP,
+}
+
+impl PreviewFormat {
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::WebP => ImageFormat::WebP,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.image_format().extensions_str()[0]
+    }
+
+    /// Encodes `img` to `target` in this format, tagged with the ICC
+    /// `profile` of its source. WebP previews are written without one.
+    pub fn save(&self, img: &DynamicImage, target: &Path, profile: Option<&[u8]>) -> Result<()> {
+        // The WebP encoder only accepts 8-bit RGB(A), and previews have no use for alpha
+        let img = DynamicImage::ImageRgb8(img.to_rgb8());
+        match self {
+            Self::Jpeg => {
+                let mut jpeg = Vec::new();
+                img.write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+                if let Some(profile) = profile {
+                    jpeg = icc::embed_jpeg(&jpeg, profile);
+                }
+                fs::write(target, jpeg)?;
+            }
+            Self::WebP => {
+                if profile.is_some() {
+                    debug!("{}: ICC profile not kept in WebP", target.display());
+                }
+                img.save_with_format(target, self.image_format())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CropFocus {
+    /// Offset of the crop window along the vertical axis.
+    fn offset(&self, len: u32, window: u32) -> u32 {
+        match self {
+            Self::Center => (len - window) / 2,
+            Self::Top => 0,
+            Self::Bottom => len - window,
+        }
+    }
+}
+
+/// Crops `img` to the aspect ratio of `width`x`height` with the window
+/// anchored at `focus`. Horizontal crops are always centered.
+fn crop_to_aspect(img: &DynamicImage, width: u32, height: u32, focus: CropFocus) -> DynamicImage {
+    let (src_width, src_height) = (img.width(), img.height());
+    // Compare src_width / src_height against width / height without floats
+    let lhs = u64::from(src_width) * u64::from(height);
+    let rhs = u64::from(src_height) * u64::from(width);
+    if lhs > rhs {
+        let window = (rhs / u64::from(height)).max(1) as u32;
+        img.crop_imm((src_width - window) / 2, 0, window, src_height)
+    } else {
+        let window = (lhs / u64::from(width)).max(1) as u32;
+        img.crop_imm(0, focus.offset(src_height, window), src_width, window)
+    }
+}
+
+/// Computes a BlurHash placeholder for `img`.
+pub fn blurhash(img: &DynamicImage) -> Result<String> {
+    // The hash only keeps a few components, a thumbnail is plenty
+    let thumb = img.thumbnail(BLURHASH_SIZE, BLURHASH_SIZE).to_rgba8();
+    Ok(blurhash::encode(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        thumb.width(),
+        thumb.height(),
+        thumb.as_raw(),
+    )?)
+}
+
+/// Resizes `img` for a preview bounded by `limit`, never scaling it up.
+pub fn resize(
+    img: &DynamicImage,
+    limit: Resolution,
+    mode: PreviewMode,
+    focus: CropFocus,
+) -> DynamicImage {
+    let img = match mode {
+        PreviewMode::Fit => img.clone(),
+        PreviewMode::Cover => crop_to_aspect(img, limit.width, limit.height, focus),
+    };
+    let src = Resolution {
+        width: img.width(),
+        height: img.height(),
+    };
+    let target = src.fit_within(limit);
+    if target == src {
+        return img;
+    }
+    img.resize_exact(target.width, target.height, FilterType::Lanczos3)
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage, RgbaImage};
+
+    use super::{blurhash, resize, CropFocus, ExtraPreview, PreviewMode};
+    use crate::meta::Resolution;
+
+    fn limit(width: u32, height: u32) -> Resolution {
+        Resolution { width, height }
+    }
+
+    fn tall_image() -> DynamicImage {
+        // 10x40, red on top, green in the middle and blue at the bottom
+        DynamicImage::ImageRgb8(RgbImage::from_fn(10, 40, |_, y| match y {
+            0..=9 => Rgb([255, 0, 0]),
+            10..=29 => Rgb([0, 255, 0]),
+            _ => Rgb([0, 0, 255]),
+        }))
+    }
+
+    #[test]
+    fn test_fit() {
+        let img = resize(&tall_image(), limit(5, 5), PreviewMode::Fit, CropFocus::Top);
+        assert_eq!(img.dimensions(), (1, 5));
+    }
+
+    #[test]
+    fn test_cover_focus() {
+        let top = resize(
+            &tall_image(),
+            limit(10, 10),
+            PreviewMode::Cover,
+            CropFocus::Top,
+        );
+        let center = resize(
+            &tall_image(),
+            limit(10, 10),
+            PreviewMode::Cover,
+            CropFocus::Center,
+        );
+        let bottom = resize(
+            &tall_image(),
+            limit(10, 10),
+            PreviewMode::Cover,
+            CropFocus::Bottom,
+        );
+        assert_eq!(top.dimensions(), (10, 10));
+        assert_eq!(center.dimensions(), (10, 10));
+        assert_ne!(top, center);
+        assert_eq!(top.get_pixel(5, 5).0, [255, 0, 0, 255]);
+        assert_eq!(center.get_pixel(5, 5).0, [0, 255, 0, 255]);
+        assert_eq!(bottom.get_pixel(5, 5).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_cover_wide() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 10));
+        let cover = resize(&img, limit(10, 10), PreviewMode::Cover, CropFocus::Top);
+        assert_eq!(cover.dimensions(), (10, 10));
+    }
+
+    #[test]
+    fn test_no_upscale() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 10));
+        let fit = resize(&img, limit(500, 500), PreviewMode::Fit, CropFocus::Center);
+        assert_eq!(fit.dimensions(), (40, 10));
+        let cover = resize(&img, limit(500, 500), PreviewMode::Cover, CropFocus::Center);
+        assert_eq!(cover.dimensions(), (10, 10));
+    }
+
+    #[test]
+    fn test_blurhash() {
+        let hash = blurhash(&tall_image()).unwrap();
+        assert!(!hash.is_empty());
+        let pixels = blurhash::decode(&hash, 32, 18, 1.0).unwrap();
+        let decoded = RgbaImage::from_raw(32, 18, pixels).unwrap();
+        assert_eq!(decoded.dimensions(), (32, 18));
+    }
+
+    #[test]
+    fn test_extra_preview() {
+        let thumbnail: ExtraPreview = "thumbnail=256x256".parse().unwrap();
+        assert_eq!(thumbnail.name, "thumbnail");
+        assert_eq!(thumbnail.limit, limit(256, 256));
+        for invalid in [
+            "thumbnail",
+            "=256x256",
+            "a/b=1x1",
+            "screenshot=1x1",
+            "thumb=0x1",
+        ] {
+            assert!(invalid.parse::<ExtraPreview>().is_err(), "{}", invalid);
+        }
+    }
+}
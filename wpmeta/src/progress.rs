@@ -0,0 +1,40 @@
+//! Progress reporting that plays well with logging.
+
+use eyre::Result;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+
+use std::io::{stderr, IsTerminal};
+use std::sync::OnceLock;
+
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// Sets up logging from `env`, suspending progress bars while a record is
+/// printed so the two don't garble each other.
+pub fn init_logger(env: &str) -> Result<()> {
+    let logger = pretty_env_logger::formatted_builder()
+        .parse_env(env)
+        .build();
+    let level = logger.filter();
+    LogWrapper::new(bars().clone(), logger).try_init()?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Creates a bar counting processed wallpapers. The bar is hidden unless
+/// `enabled` is set and stderr, where it is drawn, is a terminal.
+///
+/// Wallpapers are found while generating, so callers grow the length with
+/// [`ProgressBar::inc_length`] as they go.
+pub fn bar(enabled: bool) -> ProgressBar {
+    if !enabled || !stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template("{spinner} [{elapsed}] {wide_bar} {pos}/{len}")
+        .expect("progress template is valid");
+    bars().add(ProgressBar::new(0).with_style(style))
+}
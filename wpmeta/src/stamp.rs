@@ -0,0 +1,65 @@
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Formats `secs` since the Unix epoch as an ISO 8601 UTC timestamp.
+fn iso8601(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Describes this version of wpmeta generating at `secs` since the Unix
+/// epoch.
+pub fn stamp_at(secs: u64) -> String {
+    format!("wpmeta {} at {}", env!("CARGO_PKG_VERSION"), iso8601(secs))
+}
+
+/// Stamp of a run now, or at `SOURCE_DATE_EPOCH` if set so builds stay
+/// reproducible.
+pub fn stamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+    stamp_at(secs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{iso8601, stamp_at};
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso8601(1792000000), "2026-10-14T17:46:40Z");
+        assert_eq!(
+            stamp_at(0),
+            format!(
+                "wpmeta {} at 1970-01-01T00:00:00Z",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Source trees for tests, shared by the library, the binary and the
+//! integration tests.
+
+use image::RgbImage;
+use tempfile::TempDir;
+
+use std::fs;
+use std::path::Path;
+
+pub static DUMMY_TREE_META: &str = r#"
+    [[authors]]
+    email = "yajuu.senpai@example.com"
+    name.default = "Yajuu Senpai"
+
+    [[wallpapers]]
+    title.default = "Kusa"
+    license = "CC BY-SA 4.0"
+    id = "Kusa"
+    path = "kusa.png"
+    "#;
+
+/// Creates a source tree with a small generated image and `meta`.
+pub fn dummy_tree(meta: &str) -> TempDir {
+    let src = tempfile::tempdir().unwrap();
+    write_dummy_dir(src.path(), meta);
+    src
+}
+
+pub fn write_dummy_dir(dir: &Path, meta: &str) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("metadata.toml"), meta).unwrap();
+    for wallpaper in toml::from_str::<wpmeta::meta::Metadata>(meta)
+        .unwrap()
+        .wallpapers()
+        .into_iter()
+        .flatten()
+    {
+        RgbImage::from_pixel(64, 36, image::Rgb([2, 60, 136]))
+            .save(dir.join(wallpaper.src()))
+            .unwrap();
+        if let Some(dark) = wallpaper.dark_src() {
+            RgbImage::from_pixel(64, 36, image::Rgb([0, 0, 0]))
+                .save(dir.join(dark))
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use image::io::Reader;
+
+use std::path::Path;
+
+use crate::doctor::Finding;
+use crate::meta::Metadata;
+use crate::walk;
+
+/// Checks that every source image exists and decodes.
+fn check_images(metas: &[Metadata]) -> Vec<Finding> {
+    let mut ret = Vec::new();
+    let mut count = 0;
+    for meta in metas {
+        let base = meta.base().unwrap_or(Path::new("."));
+        for wallpaper in meta.wallpapers().into_iter().flatten() {
+            for src in [Some(wallpaper.src()), wallpaper.dark_src()]
+                .into_iter()
+                .flatten()
+                .chain(wallpaper.resolution_srcs())
+            {
+                count += 1;
+                let path = base.join(src);
+                let decoded = Reader::open(&path)
+                    .and_then(|r| r.with_guessed_format())
+                    .map_err(|e| e.to_string())
+                    .and_then(|r| r.decode().map_err(|e| e.to_string()));
+                if let Err(e) = decoded {
+                    ret.push(Finding::fail(format!(
+                        "{}: cannot decode {}: {}",
+                        wallpaper.id(),
+                        path.display(),
+                        e
+                    )));
+                }
+            }
+        }
+    }
+    if ret.is_empty() {
+        ret.push(Finding::ok(format!("all {} images decode", count)));
+    }
+    ret
+}
+
+fn check_licenses(metas: &[Metadata]) -> Vec<Finding> {
+    let mut ret = Vec::new();
+    for wallpaper in metas
+        .iter()
+        .flat_map(|m| m.wallpapers().into_iter().flatten())
+    {
+        if let Err(e) = wallpaper.canonical_license() {
+            ret.push(Finding::fail(format!(
+                "{}: invalid SPDX license expression {:?}: {}",
+                wallpaper.id(),
+                wallpaper.license(),
+                e
+            )));
+        }
+    }
+    if ret.is_empty() {
+        ret.push(Finding::ok("all licenses are valid SPDX expressions"));
+    }
+    ret
+}
+
+fn check_ids(metas: &[Metadata]) -> Finding {
+    match walk::check_unique_ids(metas) {
+        Ok(()) => Finding::ok("all wallpaper ids and package directories are unique"),
+        Err(e) => Finding::fail(e.to_string()),
+    }
+}
+
+fn check_locales(metas: &[Metadata]) -> Vec<Finding> {
+    let mut ret: Vec<_> = metas
+        .iter()
+        .filter_map(|m| {
+            let e = m.check_locales(true).err()?;
+            let base = m.base().unwrap_or(Path::new("."));
+            Some(Finding::fail(format!("{}: {}", base.display(), e)))
+        })
+        .collect();
+    if ret.is_empty() {
+        ret.push(Finding::ok("all locales are well-formed"));
+    }
+    ret
+}
+
+/// Checks the source tree at `src` without generating anything.
+pub fn validate(src: &Path) -> Vec<Finding> {
+    // Manifests with unparsable colors or paths already fail here
+    let metas = match walk::walk(src, None) {
+        Ok(metas) => metas,
+        Err(e) => return vec![Finding::fail(format!("failed to read manifests: {}", e))],
+    };
+    let mut ret = vec![Finding::ok(format!("{} manifests parsed", metas.len()))];
+    ret.append(&mut check_images(&metas));
+    ret.append(&mut check_licenses(&metas));
+    ret.push(check_ids(&metas));
+    ret.append(&mut check_locales(&metas));
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::validate;
+    use crate::test::{dummy_tree, write_dummy_dir, DUMMY_TREE_META};
+
+    fn valid_meta() -> String {
+        DUMMY_TREE_META.replace("CC BY-SA 4.0", "CC-BY-SA-4.0")
+    }
+
+    #[test]
+    fn test_ok() {
+        let src = dummy_tree(&valid_meta());
+        let findings = validate(src.path());
+        assert!(findings.iter().all(|f| f.ok), "{:?}", findings);
+
+        let src = dummy_tree(DUMMY_TREE_META);
+        let failed: Vec<_> = validate(src.path()).into_iter().filter(|f| !f.ok).collect();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].message.starts_with("Kusa: invalid SPDX license"));
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let src = tempfile::tempdir().unwrap();
+        write_dummy_dir(&src.path().join("a"), &valid_meta());
+        write_dummy_dir(&src.path().join("b"), &valid_meta());
+        let failed: Vec<_> = validate(src.path()).into_iter().filter(|f| !f.ok).collect();
+        assert_eq!(failed.len(), 1);
+        let message = &failed[0].message;
+        assert!(message.starts_with("duplicate wallpaper id Kusa in "));
+        assert!(message.contains(&src.path().join("a").display().to_string()));
+        assert!(message.contains(&src.path().join("b").display().to_string()));
+    }
+
+    #[test]
+    fn test_missing_image() {
+        let src = dummy_tree(&valid_meta());
+        fs::remove_file(src.path().join("kusa.png")).unwrap();
+        let failed: Vec<_> = validate(src.path()).into_iter().filter(|f| !f.ok).collect();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].message.starts_with("Kusa: cannot decode"));
+    }
+}
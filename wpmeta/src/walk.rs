@@ -1,12 +1,47 @@
-use eyre::{bail, Result};
-use log::{info, warn};
+use eyre::{eyre, Result};
+use log::{debug, info, warn};
 
+use locale::Locale;
+
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
+use crate::error::WpmetaError;
 use crate::meta::Metadata;
+use crate::po::Catalog;
+
+pub(crate) static METADATA_FILE: &str = "metadata.toml";
+static PO_DIR: &str = "po";
 
-static METADATA_FILE: &str = "metadata.toml";
+/// Loads `po/<locale>.po` catalogs next to a manifest into it.
+pub fn load_catalogs(path: &Path, meta: &mut Metadata) -> Result<()> {
+    let po_dir = path.join(PO_DIR);
+    if !po_dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(po_dir)? {
+        let po_file = entry?.path();
+        if po_file.extension().map(|e| e != "po").unwrap_or(true) {
+            continue;
+        }
+        let locale = po_file
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| eyre!("invalid catalog name {}", po_file.display()))?;
+        debug!("loading translations from {}", po_file.display());
+        let catalog = Catalog::from_file(&po_file)?;
+        meta.apply_catalog(&Locale::new(locale), &catalog);
+    }
+    Ok(())
+}
+
+/// Parses a manifest, ignoring the UTF-8 BOM some Windows editors add.
+pub fn parse_meta(content: &str) -> Result<Metadata> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    Ok(toml::from_str::<Metadata>(content)?)
+}
 
 pub fn extract_meta(
     base: &Path,
@@ -26,32 +61,308 @@ pub fn extract_meta(
     Some(ret)
 }
 
-pub fn walk(path: &Path, parent: Option<&Metadata>) -> Result<Vec<Metadata>> {
-    info!("Visiting {}", path.display());
-    if !path.exists() {
-        bail!("path {:?} does not exist.", path);
+/// Reads the manifest in `path`, if there is one.
+fn read_meta(path: &Path) -> Result<Option<Metadata>> {
+    let meta_file = path.join(METADATA_FILE);
+    if !meta_file.exists() {
+        return Ok(None);
     }
-    if !path.is_dir() {
-        bail!("path {:?} is not a directory", path);
+    let meta_content = fs::read_to_string(meta_file)?;
+    let mut meta = parse_meta(&meta_content)?;
+    load_catalogs(path, &mut meta)?;
+    Ok(Some(meta))
+}
+
+/// Lazily walks a source tree, yielding complete manifests depth-first.
+///
+/// A directory is always read before its subdirectories, so the manifest
+/// it passes down for inheritance is known before any child is flattened.
+/// Siblings are visited in the order of their names, whatever order the
+/// filesystem lists them in.
+pub struct Walk {
+    pending: Vec<(PathBuf, Option<Arc<Metadata>>)>,
+}
+
+impl Walk {
+    pub fn new(path: &Path, parent: Option<Metadata>) -> Result<Self, WpmetaError> {
+        if !path.exists() {
+            return Err(WpmetaError::MissingFile(path.to_owned()));
+        }
+        if !path.is_dir() {
+            return Err(WpmetaError::NotADirectory(path.to_owned()));
+        }
+        Ok(Self {
+            pending: vec![(path.to_owned(), parent.map(Arc::new))],
+        })
     }
-    let meta_file = path.join(METADATA_FILE);
-    let meta = if meta_file.exists() {
-        let meta_content = fs::read_to_string(meta_file)?;
-        Some(toml::from_str::<Metadata>(&meta_content)?)
-    } else {
+
+    /// Visits `path`, queueing its subdirectories.
+    fn visit(&mut self, path: &Path, parent: Option<&Metadata>) -> Result<Option<Metadata>> {
+        info!("Visiting {}", path.display());
+        let mut meta = read_meta(path)?;
+        if let (Some(meta), Some(parent)) = (meta.as_mut(), parent) {
+            meta.inherit_defaults(parent);
+        }
+        let ret = extract_meta(path, meta.clone(), parent);
+        if let Some(ret) = &ret {
+            ret.check_licenses()?;
+        }
+        let meta = meta.map(Arc::new);
+        let mut children = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                children.push(entry.path());
+            }
+        }
+        children.sort();
+        // Popped from the back, so the first subdirectory is visited first
+        self.pending
+            .extend(children.into_iter().rev().map(|c| (c, meta.clone())));
+        Ok(ret)
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<Metadata>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, parent)) = self.pending.pop() {
+            match self.visit(&path, parent.as_deref()) {
+                Ok(Some(meta)) => return Some(Ok(meta)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
         None
-    };
-    let mut ret = Vec::new();
-    if let Some(flattened) = extract_meta(path, meta.clone(), parent) {
-        ret.push(flattened);
-    }
-    for path in fs::read_dir(path)? {
-        let entry = path?;
-        if !entry.file_type()?.is_dir() {
-            continue;
+    }
+}
+
+/// Directories declaring each wallpaper id, and the ids installed to each
+/// package directory, shared between parallel tasks.
+#[derive(Debug, Default)]
+pub struct WallpaperIds(Mutex<Claimed>);
+
+#[derive(Debug, Default)]
+struct Claimed {
+    ids: HashMap<String, PathBuf>,
+    packages: HashMap<PathBuf, String>,
+}
+
+impl WallpaperIds {
+    /// Records the wallpaper ids of `meta` and their package directories,
+    /// failing on any already taken, as their outputs would overwrite each
+    /// other.
+    pub fn insert(&self, meta: &Metadata) -> Result<(), WpmetaError> {
+        let base = meta.base().unwrap_or(Path::new("."));
+        let mut claimed = self.0.lock().expect("Wallpaper id lock poisoned");
+        for wallpaper in meta.wallpapers().into_iter().flatten() {
+            if let Some(existing) = claimed.ids.get(wallpaper.id()) {
+                return Err(WpmetaError::DuplicateId {
+                    id: wallpaper.id().to_owned(),
+                    first: existing.clone(),
+                    second: base.to_owned(),
+                });
+            }
+            let package = wallpaper.package_dir();
+            if let Some(existing) = claimed.packages.get(&package) {
+                return Err(WpmetaError::DuplicatePackage {
+                    path: package,
+                    first: existing.clone(),
+                    second: wallpaper.id().to_owned(),
+                });
+            }
+            claimed
+                .ids
+                .insert(wallpaper.id().to_owned(), base.to_owned());
+            claimed.packages.insert(package, wallpaper.id().to_owned());
         }
-        let mut res = walk(&entry.path(), meta.as_ref())?;
-        ret.append(&mut res);
+        Ok(())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.0
+            .lock()
+            .expect("Wallpaper id lock poisoned")
+            .ids
+            .contains_key(id)
+    }
+}
+
+/// Fails if two wallpapers in `metas` share an id or a package directory.
+pub fn check_unique_ids(metas: &[Metadata]) -> Result<(), WpmetaError> {
+    let ids = WallpaperIds::default();
+    metas.iter().try_for_each(|m| ids.insert(m))
+}
+
+pub fn walk(path: &Path, parent: Option<&Metadata>) -> Result<Vec<Metadata>> {
+    Walk::new(path, parent.cloned())?.collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{check_unique_ids, parse_meta, walk, Walk};
+    use crate::error::WpmetaError;
+    use crate::test::{write_dummy_dir, DUMMY_TREE_META};
+
+    #[test]
+    fn test_parse_bom() {
+        let content = format!("\u{feff}{}", crate::meta::test::DUMMY_META);
+        let meta = parse_meta(&content).unwrap();
+        assert_eq!(meta.wallpapers().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_walk_lazily() {
+        let src = tempfile::tempdir().unwrap();
+        let child = src.path().join("child");
+        let grandchild = child.join("broken");
+        fs::create_dir_all(&grandchild).unwrap();
+        fs::write(
+            src.path().join("metadata.toml"),
+            r#"
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.default = "Yajuu Senpai"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            child.join("metadata.toml"),
+            r#"
+            [[wallpapers]]
+            title.default = "Kusa"
+            license = "CC0-1.0"
+            id = "Kusa"
+            path = "kusa.png"
+            "#,
+        )
+        .unwrap();
+        fs::write(grandchild.join("metadata.toml"), "[[wallpapers]").unwrap();
+
+        let mut walk = Walk::new(src.path(), None).unwrap();
+        // The child is yielded before the broken manifest below it is read
+        let meta = walk.next().unwrap().unwrap();
+        assert_eq!(meta.base(), Some(child.as_path()));
+        assert_eq!(
+            meta.authors().unwrap()[0].email(),
+            "yajuu.senpai@example.com"
+        );
+        assert!(walk.next().unwrap().is_err());
+        assert!(walk.next().is_none());
+    }
+
+    #[test]
+    fn test_walk_sorted() {
+        let src = tempfile::tempdir().unwrap();
+        for name in ["c", "a/b", "a", "b"] {
+            write_dummy_dir(&src.path().join(name), DUMMY_TREE_META);
+        }
+        let bases: Vec<_> = walk(src.path(), None)
+            .unwrap()
+            .iter()
+            .map(|m| {
+                m.base()
+                    .unwrap()
+                    .strip_prefix(src.path())
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        // Subdirectories come right after their parent
+        assert_eq!(bases, ["a", "a/b", "b", "c"].map(Path::new));
+    }
+
+    #[test]
+    fn test_walk_defaults() {
+        let src = tempfile::tempdir().unwrap();
+        fs::write(
+            src.path().join("metadata.toml"),
+            r#"
+            [defaults]
+            license = "CC0-1.0"
+
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.default = "Yajuu Senpai"
+            "#,
+        )
+        .unwrap();
+        let child =
+            "[[wallpapers]]\ntitle.default = \"Kusa\"\nid = \"Kusa\"\npath = \"kusa.png\"\n";
+        fs::create_dir(src.path().join("child")).unwrap();
+        fs::write(src.path().join("child/metadata.toml"), child).unwrap();
+        let metas = walk(src.path(), None).unwrap();
+        assert_eq!(metas[0].wallpapers().unwrap()[0].license(), "CC0-1.0");
+
+        // Without defaults a wallpaper still needs a license of its own
+        let src = tempfile::tempdir().unwrap();
+        fs::write(
+            src.path().join("metadata.toml"),
+            format!(
+                "{}{}",
+                child,
+                r#"
+            [[authors]]
+            email = "yajuu.senpai@example.com"
+            name.default = "Yajuu Senpai"
+            "#
+            ),
+        )
+        .unwrap();
+        assert!(walk(src.path(), None).is_err());
+    }
+
+    #[test]
+    fn test_unique_ids() {
+        let src = tempfile::tempdir().unwrap();
+        write_dummy_dir(&src.path().join("a"), DUMMY_TREE_META);
+        let metas = walk(src.path(), None).unwrap();
+        check_unique_ids(&metas).unwrap();
+
+        write_dummy_dir(&src.path().join("b"), DUMMY_TREE_META);
+        let metas = walk(src.path(), None).unwrap();
+        let err = check_unique_ids(&metas).unwrap_err();
+        assert!(matches!(&err, WpmetaError::DuplicateId { id, .. } if id == "Kusa"));
+        let message = err.to_string();
+        assert!(message.starts_with("duplicate wallpaper id Kusa in "));
+        assert!(message.contains(&src.path().join("a").display().to_string()));
+        assert!(message.contains(&src.path().join("b").display().to_string()));
+    }
+
+    #[test]
+    fn test_unique_packages() {
+        let src = tempfile::tempdir().unwrap();
+        let meta = DUMMY_TREE_META.replace(
+            "path = ",
+            "install_path = \"wallpapers/Plants\"\n    path = ",
+        );
+        write_dummy_dir(&src.path().join("a"), &meta);
+        write_dummy_dir(&src.path().join("b"), &meta.replace("Kusa", "Hana"));
+        let metas = walk(src.path(), None).unwrap();
+        let err = check_unique_ids(&metas).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Kusa and Hana both install to \"wallpapers/Plants\""
+        );
+    }
+
+    #[test]
+    fn test_walk_errors() {
+        let src = tempfile::tempdir().unwrap();
+        let missing = src.path().join("missing");
+        assert!(matches!(
+            Walk::new(&missing, None),
+            Err(WpmetaError::MissingFile(path)) if path == missing
+        ));
+        let file = src.path().join("metadata.toml");
+        fs::write(&file, "").unwrap();
+        assert!(matches!(
+            Walk::new(&file, None),
+            Err(WpmetaError::NotADirectory(_))
+        ));
     }
-    Ok(ret)
 }
@@ -0,0 +1,32 @@
+use wpmeta::generate::Target;
+use wpmeta::paths::DEFAULT_PREFIX;
+use wpmeta::{generate_all, GenerateOptions};
+
+#[path = "../src/test.rs"]
+mod fixtures;
+
+use fixtures::{dummy_tree, DUMMY_TREE_META};
+
+#[test]
+fn test_generate_all() {
+    let src = dummy_tree(DUMMY_TREE_META);
+    let dst = tempfile::tempdir().unwrap();
+    let opts = GenerateOptions {
+        targets: vec![Target::Kde, Target::Gnome],
+        ..Default::default()
+    };
+    let generation = generate_all(src.path(), dst.path(), &opts).unwrap();
+    let prefix = dst.path().join(DEFAULT_PREFIX);
+    for file in [
+        "wallpapers/Kusa/metadata.json",
+        "wallpapers/Kusa/contents/images/64x36.png",
+        "wallpapers/Kusa/contents/screenshot.jpg",
+        "gnome-background-properties/Kusa.xml",
+    ] {
+        assert!(prefix.join(file).is_file(), "{} should exist", file);
+    }
+    assert!(!prefix.join("wpmeta").exists());
+    let report: serde_json::Value =
+        serde_json::from_str(&generation.report.to_json().unwrap()).unwrap();
+    assert_eq!(report["Kusa"]["manifests"].as_array().unwrap().len(), 2);
+}